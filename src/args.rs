@@ -2,25 +2,169 @@ use std::env::args;
 use std::path::PathBuf;
 
 use anyhow::{bail, Context, Result};
-use path_absolutize::Absolutize;
+use regex::Regex;
 
+use crate::config::Config;
+use crate::misc::{parse_seconds, ToStr};
 use crate::mode::Mode;
+use crate::path::Absolute;
+use crate::revision::Revision;
+use crate::shadowcopy::ShadowCopyPath;
+use crate::zfs::{Dedup, ScanOptions, SnapshotFilter, SnapshotPath};
 
 #[derive(Debug)]
 pub(crate) struct Arguments {
     pub(crate) mode: Mode,
     pub(crate) filename: PathBuf,
+    pub(crate) filter: SnapshotFilter,
+    pub(crate) dedup: Dedup,
+    pub(crate) bisect: bool,
+    pub(crate) skip_unchanged: bool,
+    /// Whether to look for the file in the trash first.
+    pub(crate) search_trash: bool,
+    pub(crate) scan: ScanOptions,
+    /// The mountpoint of the dataset, bypassing its discovery.
+    pub(crate) dataset_root: Option<PathBuf>,
+    /// The directory containing the snapshots of `dataset_root`.
+    pub(crate) snapshot_dir: Option<PathBuf>,
 }
 
 impl Arguments {
-    pub(crate) fn get_args() -> Result<Self> {
-        let mut raw_args: Vec<_> = args().skip(1).rev().collect();
-        if raw_args.is_empty() {
-            bail!("Missing arguments");
+    pub(crate) fn get_args(conf: &Config) -> Result<Self> {
+        Self::parse(args().skip(1), conf)
+    }
+
+    fn parse(mut raw_args: impl Iterator<Item = String>, conf: &Config) -> Result<Self> {
+        let mut mode = Mode::MostRecentVersion;
+        let mut filter = SnapshotFilter::default();
+        let mut filename: Option<String> = None;
+        let mut literal = false;
+        let mut dedup = conf.dedup;
+        let mut bisect = conf.bisect;
+        let mut skip_unchanged = conf.skip_unchanged;
+        let mut search_trash = conf.search_trash;
+        let mut scan = conf.scan;
+        let mut dataset_root = None;
+        let mut snapshot_dir = None;
+
+        while let Some(arg) = raw_args.next() {
+            match arg.as_str() {
+                "-V" => mode = Mode::ChooseVersionInteractively,
+                "--literal" => literal = true,
+                "--bisect" => bisect = true,
+                "--skip-unchanged" => skip_unchanged = true,
+                "--no-trash" => search_trash = false,
+                "--only" => filter.only_classes = get_list(&mut raw_args, &arg)?,
+                "--exclude-class" => filter.exclude_classes = get_list(&mut raw_args, &arg)?,
+                "--include" => filter.include = Some(get_regex(&mut raw_args, &arg)?),
+                "--exclude" => filter.exclude = Some(get_regex(&mut raw_args, &arg)?),
+                "--dedup" => dedup = get_value(&mut raw_args, &arg)?.parse()?,
+                "--threads" => scan.threads = get_threads(&mut raw_args, &arg)?,
+                "--time-budget" => {
+                    scan.time_budget = Some(parse_seconds(&get_value(&mut raw_args, &arg)?)?)
+                }
+                "--dataset-root" => dataset_root = Some(get_path(&mut raw_args, &arg)?),
+                "--snapshot-dir" => snapshot_dir = Some(get_path(&mut raw_args, &arg)?),
+                _ if filename.is_none() => filename = Some(arg),
+                _ => bail!("unexpected argument '{arg}'"),
+            }
+        }
+
+        let filename = filename.context("filename missing")?;
+        if snapshot_dir.is_some() && dataset_root.is_none() {
+            bail!("--snapshot-dir requires --dataset-root");
+        }
+        let (filename, revision) = parse_filename(&filename, literal, conf)?;
+
+        if let Some(revision) = revision {
+            if matches!(mode, Mode::ChooseVersionInteractively) {
+                bail!("-V cannot be combined with a revision");
+            }
+            mode = Mode::SpecificVersion(revision);
         }
-        let mode = Mode::get_from_args(&mut raw_args);
-        let filename_relative: PathBuf = raw_args.pop().context("filename missing")?.into();
-        let filename = filename_relative.absolutize()?.into();
-        Ok(Self { mode, filename })
+
+        let filename = filename.make_absolute()?;
+        Ok(Self {
+            mode,
+            filename,
+            filter,
+            dedup,
+            bisect,
+            skip_unchanged,
+            search_trash,
+            scan,
+            dataset_root,
+            snapshot_dir,
+        })
+    }
+}
+
+/// Split the filename into the path of the file and the revision selected by it, if any. The
+/// revision is either given explicitly as `<file>@<revision>`, by a path inside a snapshot or by a
+/// Samba `@GMT-` token.
+fn parse_filename(
+    filename: &str,
+    literal: bool,
+    conf: &Config,
+) -> Result<(PathBuf, Option<Revision>)> {
+    if literal {
+        return Ok((filename.into(), None));
+    }
+
+    let shadow_copy =
+        ShadowCopyPath::parse(filename, &conf.samba_shares, &conf.shadow_token_format)?;
+    let named = shadow_copy.snapshot_time(conf.shadow_localtime);
+    if let Some((created, named)) = shadow_copy.time.zip(named) {
+        return Ok((
+            shadow_copy.path,
+            Some(Revision::ExactTime { created, named }),
+        ));
     }
+
+    let (filename, revision) =
+        Revision::split_from_path(shadow_copy.path.as_path().to_str_anyhow()?)?;
+
+    // a path inside a snapshot selects that snapshot
+    let revision = match (SnapshotPath::parse(&filename), revision) {
+        (Some(_), Some(_)) => bail!("a path inside a snapshot cannot have a revision"),
+        (Some(snapshot_path), None) => Some(Revision::Snapshot(snapshot_path.snapshot)),
+        (None, revision) => revision,
+    };
+    Ok((filename, revision))
+}
+
+/// Get the value following a flag.
+fn get_value(raw_args: &mut impl Iterator<Item = String>, flag: &str) -> Result<String> {
+    raw_args
+        .next()
+        .with_context(|| format!("missing value for {flag}"))
+}
+
+/// Get the path following a flag, made absolute.
+fn get_path(raw_args: &mut impl Iterator<Item = String>, flag: &str) -> Result<PathBuf> {
+    PathBuf::from(get_value(raw_args, flag)?).make_absolute()
+}
+
+/// Get a comma-separated list following a flag.
+fn get_list(raw_args: &mut impl Iterator<Item = String>, flag: &str) -> Result<Vec<String>> {
+    Ok(get_value(raw_args, flag)?
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(str::to_owned)
+        .collect())
+}
+
+/// Get a positive number of threads following a flag.
+fn get_threads(raw_args: &mut impl Iterator<Item = String>, flag: &str) -> Result<usize> {
+    match get_value(raw_args, flag)?.parse() {
+        Ok(0) | Err(_) => bail!("{flag} expects a positive number"),
+        Ok(threads) => Ok(threads),
+    }
+}
+
+/// Get a regular expression following a flag.
+fn get_regex(raw_args: &mut impl Iterator<Item = String>, flag: &str) -> Result<Regex> {
+    let value = get_value(raw_args, flag)?;
+    Regex::new(&value).with_context(|| format!("invalid regular expression for {flag}"))
 }
@@ -3,6 +3,8 @@ use smart_default::SmartDefault;
 
 use super::configparser::ConfigParser;
 use super::misc::get_config_file;
+use crate::misc::parse_seconds;
+use crate::zfs::{Dedup, RemoteCommand, ScanOptions, ZfsCommand};
 
 #[derive(Debug, SmartDefault)]
 pub(crate) struct Config {
@@ -11,6 +13,72 @@ pub(crate) struct Config {
 
     #[default(vec!["-hl".to_string()])]
     pub(crate) ls_args: Vec<String>,
+
+    /// Additional templates for snapshot names, tried before the built-in ones.
+    pub(crate) snapshot_formats: Vec<String>,
+
+    /// Samba shares as `<name>=<local path>`, to map UNC paths to local paths.
+    pub(crate) samba_shares: Vec<String>,
+
+    /// The format of snapshot names used by Samba's `vfs_shadow_copy2` (`shadow:format`).
+    pub(crate) shadow_format: String,
+
+    /// Whether the times in snapshot names are local time rather than UTC (`shadow:localtime`).
+    pub(crate) shadow_localtime: bool,
+
+    /// The format of the tokens `vfs_shadow_copy2` inserts into paths for "Previous Versions".
+    #[default("@GMT-%Y.%m.%d-%H.%M.%S".to_string())]
+    pub(crate) shadow_token_format: String,
+
+    /// How to tell versions of a file apart.
+    pub(crate) dedup: Dedup,
+
+    /// Whether to find the newest version by bisection.
+    pub(crate) bisect: bool,
+
+    /// Whether to skip snapshots `zfs` reports as unchanged, to avoid mounting them.
+    pub(crate) skip_unchanged: bool,
+
+    /// Whether to look for the file in the trash before searching snapshots.
+    #[default(true)]
+    pub(crate) search_trash: bool,
+
+    /// Whether to unmount the snapshots that were mounted by probing them, if permitted.
+    #[default(true)]
+    pub(crate) unmount_snapshots: bool,
+
+    /// Whether to search the snapshots of the origin of a cloned dataset, too.
+    #[default(true)]
+    pub(crate) follow_origins: bool,
+
+    /// Replicas of datasets as `<dataset>=<replica>`, whose snapshots are searched, too.
+    pub(crate) replicas: Vec<String>,
+
+    /// Directory snapshot sources as `<path>=<backup root>`, like those of rsnapshot.
+    pub(crate) directory_snapshots: Vec<String>,
+
+    /// Directories of `zfs send` stream files as `<path>=<directory>`.
+    pub(crate) send_streams: Vec<String>,
+
+    /// Remote snapshots as `<path>=<host>:<directory>`, searched along with the local ones.
+    pub(crate) remotes: Vec<String>,
+
+    /// How to run commands on the hosts of `remotes`.
+    pub(crate) remote_command: RemoteCommand,
+
+    /// Whether to offer loading the key of an encrypted dataset.
+    #[default(true)]
+    pub(crate) load_keys: bool,
+
+    /// Whether to unload keys loaded by `zfs-undelete` again when done.
+    #[default(true)]
+    pub(crate) unload_keys: bool,
+
+    /// How to run `zfs`.
+    pub(crate) zfs: ZfsCommand,
+
+    /// How many snapshots to probe at once and for how long.
+    pub(crate) scan: ScanOptions,
 }
 
 impl Config {
@@ -25,12 +93,28 @@ impl Config {
         }
     }
 
+    /// All user-defined templates for snapshot names, the Samba format first.
+    pub(crate) fn snapshot_name_templates(&self) -> Vec<String> {
+        Some(&self.shadow_format)
+            .filter(|f| !f.is_empty())
+            .into_iter()
+            .chain(&self.snapshot_formats)
+            .cloned()
+            .collect()
+    }
+
     /// Takes ownership of the instance and returns itself, but sanity-checked and wrapped in
     /// `anyhow::Result<_>`.
     fn sanity_checked(self) -> Result<Self> {
         if self.ls_command.is_empty() {
             bail!("missing value for LsCommand");
         }
+        if self.remote_command.args.is_empty() {
+            bail!("missing value for RemoteCommand");
+        }
+        if self.scan.threads == 0 {
+            bail!("Threads must be at least 1");
+        }
         Ok(self)
     }
 
@@ -38,6 +122,41 @@ impl Config {
     fn fill_from_parser(mut self, parser: ConfigParser) -> Result<Config> {
         parser.get_value_into("LsCommand", &mut self.ls_command);
         parser.get_values_into("LsArgs", &mut self.ls_args);
+        parser.get_values_into("SnapshotFormats", &mut self.snapshot_formats);
+        parser.get_values_into("SambaShares", &mut self.samba_shares);
+        parser.get_values_into("Replicas", &mut self.replicas);
+        parser.get_values_into("DirectorySnapshots", &mut self.directory_snapshots);
+        parser.get_values_into("SendStreams", &mut self.send_streams);
+        parser.get_values_into("Remotes", &mut self.remotes);
+        parser.get_values_into("RemoteCommand", &mut self.remote_command.args);
+        parser.get_value_into("ShadowFormat", &mut self.shadow_format);
+        parser.get_bool_into("ShadowLocaltime", &mut self.shadow_localtime)?;
+        parser.get_value_into("ShadowTokenFormat", &mut self.shadow_token_format);
+
+        parser.get_bool_into("Bisect", &mut self.bisect)?;
+        parser.get_bool_into("SkipUnchanged", &mut self.skip_unchanged)?;
+        parser.get_bool_into("SearchTrash", &mut self.search_trash)?;
+        parser.get_bool_into("UnmountSnapshots", &mut self.unmount_snapshots)?;
+        parser.get_bool_into("FollowOrigins", &mut self.follow_origins)?;
+        parser.get_bool_into("LoadKeys", &mut self.load_keys)?;
+        parser.get_parsed_into("ZfsCommand", &mut self.zfs.binary)?;
+        parser.get_values_into("ZfsPrefix", &mut self.zfs.prefix);
+        parser.get_bool_into("UnloadKeys", &mut self.unload_keys)?;
+
+        parser.get_parsed_into("Dedup", &mut self.dedup)?;
+
+        parser.get_parsed_into("Threads", &mut self.scan.threads)?;
+        let mut time_budget = String::new();
+        parser.get_value_into("TimeBudget", &mut time_budget);
+        if !time_budget.is_empty() {
+            self.scan.time_budget =
+                Some(parse_seconds(&time_budget).context("parsing TimeBudget")?);
+        }
+        let mut slow_mount = String::new();
+        parser.get_value_into("SlowMount", &mut slow_mount);
+        if !slow_mount.is_empty() {
+            self.scan.slow_threshold = parse_seconds(&slow_mount).context("parsing SlowMount")?;
+        }
 
         self.sanity_checked()
     }
@@ -1,8 +1,10 @@
 use std::collections::HashMap;
+use std::fmt::Display;
 use std::fs;
 use std::path::PathBuf;
+use std::str::FromStr;
 
-use anyhow::{bail, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 
 #[derive(Debug)]
 pub(super) struct ConfigParser {
@@ -16,6 +18,30 @@ impl ConfigParser {
         }
     }
 
+    pub fn get_bool_into(&self, key: &str, field: &mut bool) -> Result<()> {
+        if let Some(value) = self.key_value_pairs.get(key) {
+            *field = match value.to_lowercase().as_str() {
+                "yes" | "true" | "on" | "1" => true,
+                "no" | "false" | "off" | "0" => false,
+                _ => bail!("invalid value for {key}: '{value}', expected yes or no"),
+            };
+        }
+        Ok(())
+    }
+
+    pub fn get_parsed_into<T>(&self, key: &str, field: &mut T) -> Result<()>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        if let Some(value) = self.key_value_pairs.get(key) {
+            *field = value
+                .parse()
+                .map_err(|e| anyhow!("invalid value for {key}: {e}"))?;
+        }
+        Ok(())
+    }
+
     pub fn get_values_into(&self, key: &str, field: &mut Vec<String>) {
         if let Some(value) = self.key_value_pairs.get(key) {
             field.clear();
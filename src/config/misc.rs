@@ -12,6 +12,17 @@ fn get_xdg_config_home() -> Result<PathBuf> {
     }
 }
 
+/// Get the directory for user data, `$XDG_DATA_HOME` or `~/.local/share`.
+pub(crate) fn get_xdg_data_home() -> Result<PathBuf> {
+    if let Ok(s) = env::var("XDG_DATA_HOME") {
+        Ok(s.into())
+    } else {
+        let mut home = get_home_dir()?;
+        home.push(".local/share");
+        Ok(home)
+    }
+}
+
 /// Get the home-directory of the current user by looking up the `$HOME` environment variable.
 pub fn get_home_dir() -> Result<PathBuf> {
     Ok(env::var("HOME").context("$HOME not declared")?.into())
@@ -1,5 +1,7 @@
+#[allow(clippy::module_inception)]
 mod config;
 mod configparser;
 mod misc;
 
 pub(crate) use config::Config;
+pub(crate) use misc::get_xdg_data_home;
@@ -4,28 +4,151 @@ mod config;
 mod misc;
 mod mode;
 mod path;
+mod revision;
+mod shadowcopy;
+mod trash;
 mod ui;
 mod undelete;
 mod zfs;
 
+use std::rc::Rc;
+
 use anyhow::{bail, Context, Result};
+use mode::Mode;
+use revision::Revision;
+use trash::TrashedFile;
 use undelete::Undelete;
 
 fn main() -> Result<()> {
     let conf = config::Config::load().context("loading config")?;
-    let arguments = args::Arguments::get_args().context("processing arguments")?;
-    if arguments.filename.exists() {
+    let arguments = args::Arguments::get_args(&conf).context("processing arguments")?;
+
+    // the trash holds the newest version of a file deleted from a desktop, if any. It is offered
+    // first or, to choose a version, listed as the newest one.
+    let mut trashed = if arguments.search_trash
+        && !matches!(arguments.mode, Mode::SpecificVersion(_))
+        && arguments.filename.symlink_metadata().is_err()
+    {
+        TrashedFile::find(&arguments.filename)
+    } else {
+        None
+    };
+    if let Mode::MostRecentVersion = arguments.mode {
+        if let Some(found) = trashed.take() {
+            if found.offer(&arguments.filename, &conf)? {
+                return Ok(());
+            }
+        }
+    }
+
+    let schemes = zfs::NamingSchemes::with_templates(&conf.snapshot_name_templates())
+        .context("parsing snapshot name formats")?;
+    let provider: Rc<dyn zfs::SnapshotProvider> = match &arguments.dataset_root {
+        Some(root) => Rc::new(zfs::DirectoryProvider::new(
+            root.clone(),
+            arguments
+                .snapshot_dir
+                .clone()
+                .unwrap_or_else(|| root.join(".zfs/snapshot")),
+        )),
+        None => zfs::provider_for(
+            &arguments.filename,
+            &conf.zfs,
+            &conf.directory_snapshots,
+            &conf.send_streams,
+        )?
+        .into(),
+    };
+    let mut keys = zfs::LoadedKeys::new(conf.zfs.clone(), conf.unload_keys);
+    let found = keys.retry(conf.load_keys, || {
+        zfs::Dataset::find(&arguments.filename, &schemes, &provider)
+    });
+    let (mut dataset, to_recover_relative_to_mountpoint) = match (found, &trashed) {
+        (Ok(found), _) => found,
+        // the trash may hold the file without any snapshots to search
+        (Err(e), Some(trashed)) => {
+            eprintln!("could not search snapshots: {e:#}");
+            trashed.offer(&arguments.filename, &conf)?;
+            return Ok(());
+        }
+        (Err(e), None) => return Err(e),
+    };
+    // a dangling symlink exists, too
+    if dataset
+        .get_absolute_path(&to_recover_relative_to_mountpoint)
+        .symlink_metadata()
+        .is_ok()
+    {
         bail!("Cannot restore already existing file.");
     }
+    if provider.is_zfs() {
+        if conf.follow_origins {
+            if let Err(e) = keys.retry(conf.load_keys, || {
+                dataset.add_origin_snapshots(&conf.zfs, &schemes)
+            }) {
+                eprintln!("could not search the origin of {}: {e:#}", dataset.name);
+            }
+        }
+        if let Err(e) = keys.retry(conf.load_keys, || {
+            dataset.add_replica_snapshots(&conf.zfs, &conf.replicas, &schemes)
+        }) {
+            eprintln!("could not search the replicas of {}: {e:#}", dataset.name);
+        }
+        if let Mode::SpecificVersion(Revision::Time(_) | Revision::ExactTime { .. }) =
+            arguments.mode
+        {
+            if let Err(e) = dataset.read_creation_times(&conf.zfs) {
+                eprintln!("could not get the creation times of the snapshots: {e:#}");
+            }
+        }
+    } else if arguments.skip_unchanged {
+        bail!("--skip-unchanged is only supported for ZFS");
+    }
+    let remotes = zfs::RemoteSnapshots::new(conf.remote_command.clone());
+    if let Err(e) = dataset.add_remote_snapshots(
+        &remotes,
+        &conf.remotes,
+        &to_recover_relative_to_mountpoint,
+        &schemes,
+    ) {
+        eprintln!(
+            "could not search the remote snapshots of {}: {e:#}",
+            dataset.name
+        );
+    }
+    dataset.apply_filter(&arguments.filter);
+    // after the filter, so that a snapshot is only skipped if one kept holds the same files
+    if arguments.skip_unchanged {
+        dataset
+            .skip_unchanged_snapshots(&conf.zfs)
+            .context("asking zfs for unchanged snapshots")?;
+    }
+    if let Err(e) = dataset.fetch_remote_files(&remotes, &arguments.mode) {
+        eprintln!("could not fetch the file from the remote snapshots: {e:#}");
+    }
+    dataset.set_dedup(arguments.dedup);
+    dataset.set_bisect(arguments.bisect);
+    dataset.set_scan_options(arguments.scan);
 
-    let (dataset, to_recover_relative_to_mountpoint) = zfs::Dataset::find(&arguments.filename)?;
+    let automounts = if conf.unmount_snapshots && provider.is_zfs() {
+        Some(zfs::Automounts::track(&dataset, &conf.zfs).context("reading mounted snapshots")?)
+    } else {
+        None
+    };
 
     let undelete = Undelete::new(
         dataset,
+        trashed,
         to_recover_relative_to_mountpoint,
         conf,
         arguments.mode,
     );
 
-    undelete.run()
+    let result = undelete.run();
+    if let Some(automounts) = automounts {
+        if let Err(e) = automounts.unmount_new() {
+            eprintln!("could not unmount snapshots: {e:#}");
+        }
+    }
+    result
 }
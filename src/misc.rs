@@ -1,4 +1,5 @@
 use std::path::Path;
+use std::time::Duration;
 
 use anyhow::{Context, Result};
 
@@ -11,3 +12,11 @@ impl ToStr for &Path {
         self.to_str().context("could not convert path to string")
     }
 }
+
+/// Parse a duration given in seconds, like `2.5`.
+pub(crate) fn parse_seconds(s: &str) -> Result<Duration> {
+    let seconds: f64 = s
+        .parse()
+        .with_context(|| format!("'{s}' is not a number of seconds"))?;
+    Duration::try_from_secs_f64(seconds).with_context(|| format!("invalid duration '{s}'"))
+}
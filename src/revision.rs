@@ -0,0 +1,166 @@
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+use chrono::{Duration, Local, NaiveDate, NaiveDateTime, NaiveTime};
+
+/// Selects a single version of a file, given as `<file>@<revision>`.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Revision {
+    /// `file@<snapshot>`: the snapshot with this name, or the only one starting with it.
+    Snapshot(String),
+    /// `file@{<time>}`: the newest snapshot taken at or before the time.
+    Time(NaiveDateTime),
+    /// `file@~<n>`: the n-th unique version, counting from the newest one as 0.
+    Nth(usize),
+    /// A Samba `@GMT-` token: the snapshot created exactly at this time in UTC, or else the one
+    /// whose name encodes the time `named`, which is local time with `shadow:localtime`.
+    ExactTime {
+        created: NaiveDateTime,
+        named: NaiveDateTime,
+    },
+}
+
+impl Revision {
+    /// Split `<file>@<revision>` into the file and the revision. The revision is only looked for in
+    /// the last component of the path.
+    pub(crate) fn split_from_path(path: &str) -> Result<(PathBuf, Option<Self>)> {
+        let file_name_start = path.rfind('/').map_or(0, |i| i + 1);
+        let (file, revision) = match path[file_name_start..].rfind('@') {
+            Some(i) if i > 0 => path.split_at(file_name_start + i),
+            _ => return Ok((path.into(), None)),
+        };
+        let revision = &revision[1..];
+        if revision.is_empty() {
+            bail!("empty revision after '@' in '{path}'");
+        }
+        let revision = Self::parse(revision, Local::now().naive_local())
+            .with_context(|| format!("invalid revision '{revision}'"))?;
+        Ok((file.into(), Some(revision)))
+    }
+
+    fn parse(revision: &str, now: NaiveDateTime) -> Result<Self> {
+        if let Some(n) = revision.strip_prefix('~') {
+            let n = n.parse().context("expected a number after '~'")?;
+            Ok(Self::Nth(n))
+        } else if let Some(time) = revision.strip_prefix('{') {
+            let time = time.strip_suffix('}').context("missing closing '}'")?;
+            Ok(Self::Time(parse_time(time, now)?))
+        } else {
+            Ok(Self::Snapshot(revision.to_owned()))
+        }
+    }
+}
+
+/// Parse an absolute time like `2022-11-14 07:30` or a relative time like `2 days ago`.
+fn parse_time(time: &str, now: NaiveDateTime) -> Result<NaiveDateTime> {
+    let time = time.trim();
+    let midnight = |date: NaiveDate| date.and_time(NaiveTime::MIN);
+
+    match time {
+        "now" => return Ok(now),
+        "yesterday" => return Ok(now - Duration::days(1)),
+        _ => (),
+    }
+
+    if let Some(relative) = time.strip_suffix("ago") {
+        let (amount, unit) = relative
+            .trim()
+            .split_once(char::is_whitespace)
+            .context("expected '<number> <unit> ago'")?;
+        let amount: i64 = amount.parse().context("expected a number")?;
+        let duration = match unit.trim().trim_end_matches('s') {
+            "second" | "sec" => Duration::seconds(amount),
+            "minute" | "min" => Duration::minutes(amount),
+            "hour" => Duration::hours(amount),
+            "day" => Duration::days(amount),
+            "week" => Duration::weeks(amount),
+            "month" => Duration::days(30 * amount),
+            "year" => Duration::days(365 * amount),
+            other => bail!("unknown unit of time '{other}'"),
+        };
+        return Ok(now - duration);
+    }
+
+    for format in ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M:%S"] {
+        if let Ok(result) = NaiveDateTime::parse_from_str(time, format) {
+            return Ok(result);
+        }
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(time, "%Y-%m-%d") {
+        // a date without time includes the whole day
+        return Ok(midnight(date) + Duration::days(1) - Duration::seconds(1));
+    }
+
+    bail!("could not understand time '{time}'")
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use chrono::{NaiveDate, NaiveDateTime};
+
+    use super::{parse_time, Revision};
+
+    fn at(ymd: (i32, u32, u32), hms: (u32, u32, u32)) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(ymd.0, ymd.1, ymd.2)
+            .unwrap()
+            .and_hms_opt(hms.0, hms.1, hms.2)
+            .unwrap()
+    }
+
+    #[test]
+    fn split_revision_from_path() {
+        assert_eq!(
+            Revision::split_from_path("dir/report.txt@znap_2022-11-14").unwrap(),
+            (
+                PathBuf::from("dir/report.txt"),
+                Some(Revision::Snapshot("znap_2022-11-14".to_owned()))
+            )
+        );
+        assert_eq!(
+            Revision::split_from_path("report.txt@~2").unwrap(),
+            (PathBuf::from("report.txt"), Some(Revision::Nth(2)))
+        );
+        assert_eq!(
+            Revision::split_from_path("user@host/report.txt").unwrap(),
+            (PathBuf::from("user@host/report.txt"), None)
+        );
+        assert_eq!(
+            Revision::split_from_path("dir/@hidden").unwrap(),
+            (PathBuf::from("dir/@hidden"), None)
+        );
+        assert!(Revision::split_from_path("report.txt@").is_err());
+        assert!(Revision::split_from_path("report.txt@~x").is_err());
+        assert!(Revision::split_from_path("report.txt@{2 days").is_err());
+    }
+
+    #[test]
+    fn parse_times() {
+        let now = at((2022, 11, 16), (12, 0, 0));
+
+        assert_eq!(parse_time("now", now).unwrap(), now);
+        assert_eq!(
+            parse_time("yesterday", now).unwrap(),
+            at((2022, 11, 15), (12, 0, 0))
+        );
+        assert_eq!(
+            parse_time("2 days ago", now).unwrap(),
+            at((2022, 11, 14), (12, 0, 0))
+        );
+        assert_eq!(
+            parse_time("1 hour ago", now).unwrap(),
+            at((2022, 11, 16), (11, 0, 0))
+        );
+        assert_eq!(
+            parse_time("2022-11-14 07:30", now).unwrap(),
+            at((2022, 11, 14), (7, 30, 0))
+        );
+        assert_eq!(
+            parse_time("2022-11-14", now).unwrap(),
+            at((2022, 11, 14), (23, 59, 59))
+        );
+        assert!(parse_time("2 fortnights ago", now).is_err());
+        assert!(parse_time("last tuesday", now).is_err());
+    }
+}
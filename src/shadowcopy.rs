@@ -0,0 +1,135 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use chrono::{Local, NaiveDateTime, TimeZone};
+
+/// A path as handed out by Samba, like `\\server\share\@GMT-2022.11.14-07.30.00\dir\file.xlsx`.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct ShadowCopyPath {
+    /// The local path of the file, without the token.
+    pub(crate) path: PathBuf,
+    /// The time of the snapshot in UTC, taken from the token.
+    pub(crate) time: Option<NaiveDateTime>,
+}
+
+impl ShadowCopyPath {
+    /// Find a `@GMT-` token in the path, in the format `token_format`. UNC paths are mapped to
+    /// local paths using the shares, given as `<name>=<local path>`.
+    pub(crate) fn parse(path: &str, shares: &[String], token_format: &str) -> Result<Self> {
+        let local_path =
+            if let Some(unc) = path.strip_prefix(r"\\").or_else(|| path.strip_prefix("//")) {
+                map_unc_path(unc, shares)?
+            } else {
+                PathBuf::from(path)
+            };
+
+        let mut time = None;
+        let mut result = PathBuf::new();
+        for component in local_path.iter() {
+            let token = component
+                .to_str()
+                .and_then(|c| NaiveDateTime::parse_from_str(c, token_format).ok());
+            match (token, time) {
+                (Some(_), Some(_)) => bail!("more than one @GMT token in '{path}'"),
+                (Some(token), None) => time = Some(token),
+                (None, _) => result.push(component),
+            }
+        }
+
+        Ok(Self { path: result, time })
+    }
+
+    /// The time of the snapshot as encoded in snapshot names, which are in UTC unless `localtime`
+    /// is set. This follows `shadow:localtime` of `vfs_shadow_copy2`.
+    pub(crate) fn snapshot_time(&self, localtime: bool) -> Option<NaiveDateTime> {
+        if localtime {
+            self.time.map(|t| Local.from_utc_datetime(&t).naive_local())
+        } else {
+            self.time
+        }
+    }
+}
+
+/// Map `server\share\rest` to the local directory of the share, followed by the rest.
+fn map_unc_path(unc: &str, shares: &[String]) -> Result<PathBuf> {
+    let mut components = unc.split(['\\', '/']).filter(|c| !c.is_empty());
+    let _server = components
+        .next()
+        .context("UNC path is missing the server")?;
+    let share = components.next().context("UNC path is missing the share")?;
+
+    let share_path = shares
+        .iter()
+        .filter_map(|s| s.split_once('='))
+        .find(|(name, _)| name.eq_ignore_ascii_case(share))
+        .map(|(_, local)| Path::new(local))
+        .with_context(|| format!("unknown share '{share}', add it to SambaShares in the config"))?;
+
+    Ok(components.fold(share_path.to_path_buf(), |path, c| path.join(c)))
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use chrono::NaiveDate;
+
+    use super::ShadowCopyPath;
+
+    const FORMAT: &str = "@GMT-%Y.%m.%d-%H.%M.%S";
+
+    #[test]
+    fn parse_shadow_copy_paths() {
+        let time = NaiveDate::from_ymd_opt(2022, 11, 14)
+            .unwrap()
+            .and_hms_opt(7, 30, 0)
+            .unwrap();
+        let shares = vec!["projects=/tank/projects".to_owned()];
+
+        assert_eq!(
+            ShadowCopyPath::parse(
+                r"\\server\Projects\@GMT-2022.11.14-07.30.00\dir\file.xlsx",
+                &shares,
+                FORMAT
+            )
+            .unwrap(),
+            ShadowCopyPath {
+                path: PathBuf::from("/tank/projects/dir/file.xlsx"),
+                time: Some(time),
+            }
+        );
+        assert_eq!(
+            ShadowCopyPath::parse(
+                "/tank/projects/@GMT-2022.11.14-07.30.00/file.xlsx",
+                &[],
+                FORMAT
+            )
+            .unwrap(),
+            ShadowCopyPath {
+                path: PathBuf::from("/tank/projects/file.xlsx"),
+                time: Some(time),
+            }
+        );
+        assert_eq!(
+            ShadowCopyPath::parse(r"\\server\projects\file.xlsx", &shares, FORMAT).unwrap(),
+            ShadowCopyPath {
+                path: PathBuf::from("/tank/projects/file.xlsx"),
+                time: None,
+            }
+        );
+        assert!(ShadowCopyPath::parse(
+            r"\\server\other\@GMT-2022.11.14-07.30.00\f",
+            &shares,
+            FORMAT
+        )
+        .is_err());
+
+        // a custom token format
+        assert_eq!(
+            ShadowCopyPath::parse("/tank/projects/@2022-11-14T07:30/f", &[], "@%Y-%m-%dT%H:%M")
+                .unwrap()
+                .time,
+            Some(time)
+        );
+    }
+}
@@ -0,0 +1,283 @@
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::os::unix::ffi::OsStringExt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::NaiveDateTime;
+
+use crate::cmd::{copy, ls};
+use crate::config::{get_xdg_data_home, Config};
+use crate::ui;
+use crate::zfs::Mount;
+
+/// A file in a freedesktop.org trash directory, which keeps the trashed file in `files` and where
+/// it came from in a `.trashinfo` file in `info`.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct TrashedFile {
+    /// The trashed file in `files`.
+    path: PathBuf,
+    /// The `.trashinfo` file in `info`.
+    info: PathBuf,
+    deletion_date: Option<NaiveDateTime>,
+}
+
+impl TrashedFile {
+    /// Find the most recently trashed file that was at the absolute `path`, in the home trash and
+    /// the trash directories at the top of the mount containing `path`. Trash directories that
+    /// cannot be searched are skipped with a warning.
+    pub(crate) fn find(path: &Path) -> Option<Self> {
+        let mut found = vec![];
+        for (trash, top) in trash_dirs(path) {
+            found.extend(read_trash_dir(&trash, top.as_deref(), path));
+        }
+        found.into_iter().max_by_key(|f| f.deletion_date)
+    }
+
+    /// The trashed file in `files`.
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Describe the file as a version, by when it was deleted.
+    pub(crate) fn describe(&self) -> String {
+        match self.deletion_date {
+            Some(date) => format!("trash, deleted {}", date.format("%Y-%m-%d %H:%M:%S")),
+            None => "trash".to_owned(),
+        }
+    }
+
+    /// Show the trashed file and restore it to `target` if the user agrees. Return whether it was
+    /// restored.
+    pub(crate) fn offer(&self, target: &Path, conf: &Config) -> Result<bool> {
+        println!(
+            "found file in {}:\n  {}",
+            self.describe(),
+            self.path.display()
+        );
+        ls(&self.path, &conf.ls_command, &conf.ls_args)?;
+
+        if !ui::user_agrees("Restore from trash?")? {
+            return Ok(false);
+        }
+        self.restore(target)?;
+        Ok(true)
+    }
+
+    /// Move the file out of the trash to `target` and remove its `.trashinfo` file.
+    pub(crate) fn restore(&self, target: &Path) -> Result<()> {
+        if let Err(e) = fs::rename(&self.path, target) {
+            // the home trash may be on another filesystem
+            if e.raw_os_error() != Some(libc::EXDEV) {
+                return Err(e).with_context(|| format!("could not move {:?}", self.path));
+            }
+            copy(&self.path, target)?;
+            if self.path.symlink_metadata()?.is_dir() {
+                fs::remove_dir_all(&self.path)?;
+            } else {
+                fs::remove_file(&self.path)?;
+            }
+        }
+        fs::remove_file(&self.info).with_context(|| format!("could not remove {:?}", self.info))
+    }
+}
+
+/// The trash directories that may hold the file at `path`, each with the top directory its
+/// relative paths refer to.
+fn trash_dirs(path: &Path) -> Vec<(PathBuf, Option<PathBuf>)> {
+    let mut result = vec![];
+    if let Ok(data_home) = get_xdg_data_home() {
+        result.push((data_home.join("Trash"), None));
+    }
+
+    let mounts = match Mount::read_all() {
+        Ok(mounts) => mounts,
+        Err(e) => {
+            eprintln!("could not search the trash of the mount containing the file: {e:#}");
+            return result;
+        }
+    };
+    if let Some(mount) = Mount::containing(&mounts, path) {
+        let top = &mount.mount_point;
+        // SAFETY: `getuid` has no preconditions and cannot fail
+        let uid = unsafe { libc::getuid() };
+        // `.Trash` must be a real directory with the sticky bit set, not a symlink
+        let shared = top.join(".Trash");
+        if shared
+            .symlink_metadata()
+            .is_ok_and(|m| m.is_dir() && m.permissions().mode() & 0o1000 != 0)
+        {
+            result.push((shared.join(uid.to_string()), Some(top.clone())));
+        }
+        result.push((top.join(format!(".Trash-{uid}")), Some(top.clone())));
+    }
+    result
+}
+
+/// Find the files in the trash directory that were at `path`. Relative paths in `.trashinfo`
+/// files are relative to `top`. A missing trash directory is skipped silently, other errors with
+/// a warning.
+fn read_trash_dir(trash: &Path, top: Option<&Path>, path: &Path) -> Vec<TrashedFile> {
+    let info_dir = trash.join("info");
+    let entries = match info_dir.read_dir() {
+        Ok(entries) => entries,
+        Err(e) => {
+            warn_unless_missing(&info_dir, &e);
+            return vec![];
+        }
+    };
+
+    entries
+        .filter_map(|entry| match entry {
+            Ok(entry) => Some(entry),
+            Err(e) => {
+                warn_unless_missing(&info_dir, &e);
+                None
+            }
+        })
+        .filter_map(|entry| {
+            let info = entry.path();
+            let name = info.file_stem()?;
+            if info.extension()? != "trashinfo" {
+                return None;
+            }
+            let content = match fs::read_to_string(&info) {
+                Ok(content) => content,
+                Err(e) => {
+                    warn_unless_missing(&info, &e);
+                    return None;
+                }
+            };
+            let (original, deletion_date) = parse_trashinfo(&content)?;
+            let original = match top {
+                Some(top) if original.is_relative() => top.join(original),
+                _ => original,
+            };
+            let trashed = trash.join("files").join(name);
+            (original == path && trashed.symlink_metadata().is_ok()).then_some(TrashedFile {
+                path: trashed,
+                info,
+                deletion_date,
+            })
+        })
+        .collect()
+}
+
+fn warn_unless_missing(path: &Path, error: &io::Error) {
+    if error.kind() != ErrorKind::NotFound {
+        eprintln!("could not search the trash, skipping {path:?}: {error}");
+    }
+}
+
+/// Parse the original path and the deletion date out of a `.trashinfo` file.
+fn parse_trashinfo(content: &str) -> Option<(PathBuf, Option<NaiveDateTime>)> {
+    let mut lines = content.lines().map(str::trim);
+    if lines.next()? != "[Trash Info]" {
+        return None;
+    }
+
+    let mut path = None;
+    let mut deletion_date = None;
+    for line in lines {
+        if let Some(value) = line.strip_prefix("Path=") {
+            path = Some(PathBuf::from(OsString::from_vec(percent_decode(value))));
+        } else if let Some(value) = line.strip_prefix("DeletionDate=") {
+            deletion_date = NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S").ok();
+        }
+    }
+    Some((path?, deletion_date))
+}
+
+/// Decode `%XX` escapes, as used in the paths of `.trashinfo` files.
+fn percent_decode(s: &str) -> Vec<u8> {
+    let mut result = Vec::with_capacity(s.len());
+    let mut bytes = s.as_bytes();
+    while let Some((&first, rest)) = bytes.split_first() {
+        let decoded = rest
+            .get(..2)
+            .filter(|_| first == b'%')
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match decoded {
+            Some(byte) => {
+                result.push(byte);
+                bytes = &rest[2..];
+            }
+            None => {
+                result.push(first);
+                bytes = rest;
+            }
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    use super::{parse_trashinfo, read_trash_dir};
+
+    #[test]
+    fn parse_trashinfo_files() {
+        let (path, date) = parse_trashinfo(
+            "[Trash Info]\nPath=/home/penguin/my%20report%25.txt\nDeletionDate=2022-11-14T07:30:00\n",
+        )
+        .unwrap();
+        assert_eq!(path, PathBuf::from("/home/penguin/my report%.txt"));
+        assert_eq!(date.unwrap().to_string(), "2022-11-14 07:30:00");
+
+        assert_eq!(
+            parse_trashinfo("[Trash Info]\nPath=docs/a\n"),
+            Some((PathBuf::from("docs/a"), None))
+        );
+        assert_eq!(parse_trashinfo("Path=/a\n"), None);
+        assert_eq!(parse_trashinfo("[Trash Info]\n"), None);
+    }
+
+    #[test]
+    fn find_and_restore_trashed_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let trash = root.join(".Trash-1000");
+        fs::create_dir_all(trash.join("info")).unwrap();
+        fs::create_dir_all(trash.join("files")).unwrap();
+        for (name, path, date, content) in [
+            ("a.txt", "docs/a.txt", "2022-11-14T07:30:00", "older"),
+            ("a.2.txt", "docs/a.txt", "2022-11-15T07:30:00", "newer"),
+            ("b.txt", "docs/b.txt", "2022-11-16T07:30:00", "other"),
+        ] {
+            fs::write(
+                trash.join("info").join(format!("{name}.trashinfo")),
+                format!("[Trash Info]\nPath={path}\nDeletionDate={date}\n"),
+            )
+            .unwrap();
+            fs::write(trash.join("files").join(name), content).unwrap();
+        }
+        // a trashinfo file whose file is gone
+        fs::write(
+            trash.join("info/c.txt.trashinfo"),
+            "[Trash Info]\nPath=docs/a.txt\nDeletionDate=2022-11-17T07:30:00\n",
+        )
+        .unwrap();
+
+        let target = root.join("docs/a.txt");
+        let newest = read_trash_dir(&trash, Some(root), &target)
+            .into_iter()
+            .max_by_key(|f| f.deletion_date)
+            .unwrap();
+        assert_eq!(newest.path, trash.join("files/a.2.txt"));
+        assert_eq!(newest.describe(), "trash, deleted 2022-11-15 07:30:00");
+        assert!(read_trash_dir(&trash, None, &target).is_empty());
+        assert!(read_trash_dir(&root.join("missing"), None, &target).is_empty());
+
+        fs::create_dir(root.join("docs")).unwrap();
+        newest.restore(&target).unwrap();
+        assert_eq!(fs::read_to_string(&target).unwrap(), "newer");
+        assert!(!trash.join("info/a.2.txt.trashinfo").exists());
+        assert!(!Path::new(&trash.join("files/a.2.txt")).exists());
+    }
+}
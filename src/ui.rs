@@ -3,7 +3,11 @@ use std::io::{self, Write};
 use anyhow::{Context, Result};
 
 pub(crate) fn user_wants_to_continue() -> Result<bool> {
-    print!("Restore? [y/N] ");
+    user_agrees("Restore?")
+}
+
+pub(crate) fn user_agrees(question: &str) -> Result<bool> {
+    print!("{question} [y/N] ");
     io::stdout().flush().context("could not flush stdout")?;
     let mut buf = String::new();
     io::stdin()
@@ -1,17 +1,22 @@
 use std::io::{stdout, Write};
 use std::path::{Path, PathBuf};
 
-use anyhow::{anyhow, bail, Result};
+use anyhow::{bail, Result};
+use itertools::Itertools;
 
 use crate::cmd::{copy, ls};
 use crate::config::Config;
 use crate::mode::Mode;
+use crate::revision::Revision;
+use crate::trash::TrashedFile;
 use crate::ui::{self, user_wants_to_continue};
-use crate::zfs::{Dataset, Snapshot};
+use crate::zfs::{Dataset, Snapshot, Version};
 
 #[derive(Debug)]
 pub(crate) struct Undelete {
     dataset: Dataset,
+    /// The file in the trash, listed as the newest version.
+    trashed: Option<TrashedFile>,
     to_recover_relative_to_mountpoint: PathBuf,
     conf: Config,
     mode: Mode,
@@ -20,12 +25,14 @@ pub(crate) struct Undelete {
 impl Undelete {
     pub(crate) fn new(
         dataset: Dataset,
+        trashed: Option<TrashedFile>,
         to_recover_relative_to_mountpoint: PathBuf,
         conf: Config,
         mode: Mode,
     ) -> Self {
         Self {
             dataset,
+            trashed,
             to_recover_relative_to_mountpoint,
             conf,
             mode,
@@ -36,92 +43,144 @@ impl Undelete {
         match self.mode {
             Mode::MostRecentVersion => self.restore_most_recent_version(),
             Mode::ChooseVersionInteractively => self.restore_interactively(),
+            Mode::SpecificVersion(ref revision) => self.restore_revision(revision),
         }
     }
 
     fn restore_most_recent_version(&self) -> Result<()> {
-        let full_path_in_snapshot = self
+        let snapshot = self
             .dataset
             .find_newest_snapshot_containing_the_file(&self.to_recover_relative_to_mountpoint)?;
 
+        self.ask_and_restore(&self.path_in_snapshot(snapshot))
+    }
+
+    fn restore_revision(&self, revision: &Revision) -> Result<()> {
+        let snapshot = self
+            .dataset
+            .resolve_revision(&self.to_recover_relative_to_mountpoint, revision)?;
+
+        self.ask_and_restore(&self.path_in_snapshot(snapshot))
+    }
+
+    /// The path of the file to recover inside the snapshot.
+    fn path_in_snapshot(&self, snapshot: &Snapshot) -> PathBuf {
+        self.dataset
+            .path_in_snapshot(snapshot, &self.to_recover_relative_to_mountpoint)
+    }
+
+    /// Show the file that was found and restore it if the user agrees.
+    fn ask_and_restore(&self, full_path_in_snapshot: &Path) -> Result<()> {
         println!("found file:\n  {}", full_path_in_snapshot.display());
 
         if ui::user_wants_to_continue()? {
             let full_path_in_dataset = self
                 .dataset
                 .get_absolute_path(&self.to_recover_relative_to_mountpoint);
-            copy(&full_path_in_snapshot, &full_path_in_dataset)?;
+            copy(full_path_in_snapshot, &full_path_in_dataset)?;
         }
         Ok(())
     }
 
+    /// List the versions, newest first, and restore the one the user chooses. A file in the trash
+    /// is the newest version.
     fn restore_interactively(&self) -> Result<()> {
-        let unique_versions = self
+        let unique_versions = match self
             .dataset
-            .get_unique_versions(&self.to_recover_relative_to_mountpoint)?;
+            .get_unique_versions(&self.to_recover_relative_to_mountpoint)
+        {
+            Ok(versions) => versions,
+            // the trash may hold the only version
+            Err(e) if self.trashed.is_some() => {
+                println!("{e}");
+                vec![]
+            }
+            Err(e) => return Err(e),
+        };
         self.show_enumerated_snapshots(&unique_versions)?;
 
-        let snapshot;
-        loop {
-            match self.choose_version(&unique_versions) {
-                Ok(snap) => {
-                    snapshot = snap;
-                    break;
-                }
+        let trashed = usize::from(self.trashed.is_some());
+        let choice = loop {
+            match self.choose_version(trashed + unique_versions.len()) {
+                Ok(choice) => break choice,
                 Err(e) => println!("{e}"),
             }
-        }
-
-        let to_restore = snapshot.join(&self.to_recover_relative_to_mountpoint);
-
-        self.restore_specific_version(&to_restore)
-    }
-
-    fn choose_version<'a>(&self, unique_versions: &Vec<&'a Snapshot>) -> Result<&'a Snapshot> {
-        let length = unique_versions.len();
-
-        let choice = if length == 1 {
-            self.ask_restore_only_snapshot()?
-        } else {
-            let choice = ui::ask_user_for_version(unique_versions.len())?;
-            if choice >= length {
-                bail!("invalid answer")
-            }
-            choice
         };
 
-        let version = unique_versions
-            .get(choice)
-            .ok_or_else(|| anyhow!("this should not happen"))?;
-
-        Ok(version)
+        match (&self.trashed, choice.checked_sub(trashed)) {
+            (_, Some(index)) => self.restore_specific_version(
+                &self.path_in_snapshot(unique_versions[index].snapshot()),
+            ),
+            (Some(trashed), None) => trashed.restore(
+                &self
+                    .dataset
+                    .get_absolute_path(&self.to_recover_relative_to_mountpoint),
+            ),
+            (None, None) => unreachable!("without the trash, every choice is a version"),
+        }
     }
 
-    fn show_enumerated_snapshots(
-        &self,
-        unique_versions: &[&Snapshot],
-    ) -> Result<(), anyhow::Error> {
-        let mut pathbuf = PathBuf::default();
+    fn choose_version(&self, length: usize) -> Result<usize> {
+        if length == 1 {
+            return self.ask_restore_only_snapshot();
+        }
+        let choice = ui::ask_user_for_version(length)?;
+        if choice >= length {
+            bail!("invalid answer")
+        }
+        Ok(choice)
+    }
 
-        let snapshot_names: Vec<_> = unique_versions
+    fn show_enumerated_snapshots(&self, unique_versions: &[Version]) -> Result<(), anyhow::Error> {
+        let entries: Vec<_> = self
+            .trashed
             .iter()
-            .map(|snap| snap.path().file_name().unwrap().to_str().unwrap())
+            .map(|trashed| (trashed.describe(), trashed.path().to_path_buf()))
+            .chain(unique_versions.iter().map(|version| {
+                (
+                    self.describe_version(version),
+                    self.path_in_snapshot(version.snapshot()),
+                )
+            }))
             .collect();
 
-        let len_longest_name = snapshot_names.iter().map(|name| name.len()).max().unwrap();
+        let len_longest_name = entries.iter().map(|(name, _)| name.len()).max().unwrap();
 
-        for (i, snap) in unique_versions.iter().enumerate() {
-            self.get_absolute_path(&mut pathbuf, snap);
-            show_snapshot(i, len_longest_name, snapshot_names[i])?;
-            ls(&pathbuf, &self.conf.ls_command, &self.conf.ls_args)?;
+        for (i, (name, path)) in entries.iter().enumerate() {
+            show_snapshot(i, len_longest_name, name)?;
+            ls(path, &self.conf.ls_command, &self.conf.ls_args)?;
         }
         Ok(())
     }
 
-    fn get_absolute_path(&self, path: &mut PathBuf, snap: &&Snapshot) {
-        path.clear();
-        path.push(snap.path());
-        path.push(&self.to_recover_relative_to_mountpoint);
+    /// Describe a version by the snapshot it is restored from. If more snapshots contain the
+    /// version, they are named as ranges of neighbouring snapshots, since a file can change and
+    /// change back. The metadata `ls` does not show is
+    /// summarized. For a directory, the changes made by the version that followed it are listed,
+    /// for a symlink its target and whether the target exists.
+    fn describe_version(&self, version: &Version) -> String {
+        let snapshots = version.snapshots();
+        let oldest = describe(version.snapshot());
+        let description = if snapshots.len() > 1 {
+            let ranges = snapshot_ranges(self.dataset.snapshots(), snapshots);
+            format!("{oldest}, snapshots {ranges}")
+        } else {
+            oldest
+        };
+
+        let mut notes = vec![version.info().summary()];
+        if let Some(changes) = version.changes() {
+            notes.push(format!("next version: {changes}"));
+        } else if let Some(target) = &version.info().symlink_target {
+            let resolves = self.dataset.symlink_resolves(
+                version.snapshot(),
+                &self.to_recover_relative_to_mountpoint,
+                target,
+            );
+            let state = if resolves { "resolves" } else { "dangling" };
+            notes.push(format!("-> {}, {state}", target.display()));
+        }
+        format!("{description} [{}]", notes.join("; "))
     }
 
     fn ask_restore_only_snapshot(&self) -> Result<usize> {
@@ -143,6 +202,49 @@ impl Undelete {
     }
 }
 
+/// Describe a snapshot by its name, followed by its class, time and description and the dataset it
+/// belongs to, if that is an origin or a replica.
+fn describe(snap: &Snapshot) -> String {
+    let details: Vec<_> = snap
+        .class()
+        .map(str::to_owned)
+        .into_iter()
+        .chain(
+            snap.time()
+                .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string()),
+        )
+        .chain(snap.description().map(str::to_owned))
+        .chain(snap.dataset().map(|dataset| format!("from {dataset}")))
+        .collect();
+
+    if details.is_empty() {
+        snap.name().to_owned()
+    } else {
+        format!("{} ({})", snap.name(), details.join(", "))
+    }
+}
+
+/// Name the snapshots as ranges of neighbours in the timeline, like `a..c, f`.
+fn snapshot_ranges(timeline: &[Snapshot], snapshots: &[&Snapshot]) -> String {
+    let mut ranges: Vec<(usize, usize)> = vec![];
+    for snapshot in snapshots {
+        let Some(index) = timeline.iter().position(|s| std::ptr::eq(s, *snapshot)) else {
+            continue;
+        };
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == index => *end = index,
+            _ => ranges.push((index, index)),
+        }
+    }
+    ranges
+        .into_iter()
+        .map(|(start, end)| match start == end {
+            true => timeline[start].name().to_owned(),
+            false => format!("{}..{}", timeline[start].name(), timeline[end].name()),
+        })
+        .join(", ")
+}
+
 fn show_snapshot(i: usize, len_longest_name: usize, name: &str) -> Result<()> {
     let required_spaces = len_longest_name - name.len();
     let spaces = " ".repeat(required_spaces);
@@ -150,3 +252,94 @@ fn show_snapshot(i: usize, len_longest_name: usize, name: &str) -> Result<()> {
     stdout().lock().flush()?;
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::path::Path;
+    use std::rc::Rc;
+
+    use super::{snapshot_ranges, Undelete};
+    use crate::config::Config;
+    use crate::mode::Mode;
+    use crate::revision::Revision;
+    use crate::zfs::{Dataset, DirectoryProvider, NamingSchemes, Snapshot, SnapshotProvider};
+
+    #[test]
+    fn name_neighbouring_snapshots_as_ranges() {
+        let timeline: Vec<_> = ["a", "b", "c", "d", "e", "f"]
+            .into_iter()
+            .map(|name| Snapshot::new(name.into(), &NamingSchemes::default()))
+            .collect();
+        let pick =
+            |indices: &[usize]| -> Vec<_> { indices.iter().map(|&i| &timeline[i]).collect() };
+        assert_eq!(snapshot_ranges(&timeline, &pick(&[0, 1, 2, 5])), "a..c, f");
+        assert_eq!(snapshot_ranges(&timeline, &pick(&[1, 3, 4])), "b, d..e");
+        assert_eq!(snapshot_ranges(&timeline, &pick(&[2])), "c");
+    }
+
+    #[test]
+    fn restore_from_directory_snapshots() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let (live, snapshots) = (root.join("live"), root.join("snapshots"));
+        fs::create_dir_all(live.join("docs")).unwrap();
+        for (name, content) in [
+            ("2022-11-14-073000", Some("old")),
+            ("2022-11-15-073000", Some("newer")),
+            ("2022-11-16-073000", None),
+        ] {
+            let dir = snapshots.join(name).join("docs");
+            fs::create_dir_all(&dir).unwrap();
+            if let Some(content) = content {
+                fs::write(dir.join("report.txt"), content).unwrap();
+            }
+        }
+        let mapping = format!("{}={}", live.display(), snapshots.display());
+        let provider: Rc<dyn SnapshotProvider> =
+            Rc::new(DirectoryProvider::parse(&mapping).unwrap());
+        let schemes = NamingSchemes::default();
+        let file = live.join("docs/report.txt");
+
+        // the newest version
+        let (dataset, relative) = Dataset::find(&file, &schemes, &provider).unwrap();
+        assert_eq!(relative, Path::new("docs/report.txt"));
+        assert_eq!(dataset.get_unique_versions(&relative).unwrap().len(), 2);
+        let newest = dataset
+            .find_newest_snapshot_containing_the_file(&relative)
+            .unwrap()
+            .clone();
+        let undelete = Undelete::new(
+            dataset,
+            None,
+            relative,
+            Config::default(),
+            Mode::MostRecentVersion,
+        );
+        undelete
+            .restore_specific_version(&undelete.path_in_snapshot(&newest))
+            .unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap(), "newer");
+        fs::remove_file(&file).unwrap();
+
+        // a path inside a snapshot
+        let in_snapshot = snapshots.join("2022-11-14-073000/docs/report.txt");
+        let (dataset, relative) = Dataset::find(&in_snapshot, &schemes, &provider).unwrap();
+        assert_eq!(relative, Path::new("docs/report.txt"));
+        let revision = Revision::Snapshot("2022-11-14-073000".to_owned());
+        let snapshot = dataset.resolve_revision(&relative, &revision).unwrap();
+        let to_restore = dataset.path_in_snapshot(snapshot, &relative);
+        assert_eq!(to_restore, in_snapshot);
+        let undelete = Undelete::new(
+            dataset,
+            None,
+            relative,
+            Config::default(),
+            Mode::SpecificVersion(revision),
+        );
+        undelete.restore_specific_version(&to_restore).unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap(), "old");
+
+        assert!(Dataset::find(Path::new("/etc/passwd"), &schemes, &provider).is_err());
+    }
+}
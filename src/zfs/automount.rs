@@ -0,0 +1,71 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+
+use super::cmd::ZfsCommand;
+use super::dataset::Dataset;
+use super::mountinfo::Mount;
+
+/// Tracks which snapshots of a dataset get automounted by probing them, so they can be unmounted
+/// again instead of lingering until the kernel expires them.
+#[derive(Debug)]
+pub(crate) struct Automounts {
+    zfs: ZfsCommand,
+    snapshot_dirs: HashSet<PathBuf>,
+    mounted_before: HashSet<PathBuf>,
+}
+
+impl Automounts {
+    /// Remember which snapshots of the dataset and its origins are currently mounted.
+    pub(crate) fn track(dataset: &Dataset, zfs: &ZfsCommand) -> Result<Self> {
+        let snapshot_dirs = dataset
+            .snapshots()
+            .iter()
+            .filter_map(|s| s.path().parent())
+            .map(Path::to_path_buf)
+            .chain([dataset.path.join(".zfs/snapshot")])
+            .collect();
+        let mounted_before = mounted_snapshots(&snapshot_dirs)?;
+        Ok(Self {
+            zfs: zfs.clone(),
+            snapshot_dirs,
+            mounted_before,
+        })
+    }
+
+    /// Unmount the snapshots that were mounted since tracking started. Only root may unmount
+    /// snapshots, so without a privilege wrapper in `ZfsPrefix` this does nothing for everybody
+    /// else.
+    pub(crate) fn unmount_new(&self) -> Result<()> {
+        // SAFETY: `geteuid` has no preconditions and cannot fail
+        if unsafe { libc::geteuid() } != 0 && !self.zfs.has_prefix() {
+            return Ok(());
+        }
+
+        let mut mounted_now: Vec<_> = mounted_snapshots(&self.snapshot_dirs)?
+            .into_iter()
+            .filter(|m| !self.mounted_before.contains(m))
+            .collect();
+        mounted_now.sort_unstable();
+        for mount_point in mounted_now {
+            if let Err(e) = self.zfs.umount(&mount_point) {
+                eprintln!("could not unmount {}: {e:#}", mount_point.display());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The mountpoints of all mounted snapshots below the snapshot directories.
+fn mounted_snapshots(snapshot_dirs: &HashSet<PathBuf>) -> Result<HashSet<PathBuf>> {
+    Ok(Mount::read_all()?
+        .into_iter()
+        .filter(|m| {
+            m.mount_point
+                .parent()
+                .is_some_and(|p| snapshot_dirs.contains(p))
+        })
+        .map(|m| m.mount_point)
+        .collect())
+}
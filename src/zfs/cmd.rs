@@ -1,40 +1,274 @@
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
-use anyhow::{Context, Result};
-
-/// Use `zfs list` to get the paths of all mounted datasets.
-pub(super) fn get_mountpoints_of_mounted_datasets() -> Result<Vec<PathBuf>> {
-    let string = run_list_command()?;
-    let result = string
-        .lines()
-        .map(|l| l.split_terminator('\t').collect::<Vec<_>>())
-        .filter(|split| {
-            split
-                .get(2)
-                .expect("has a 'mounted' column")
-                .contains("yes")
-        })
-        .map(|split| split.get(1).expect("has a 'mountpoint' column").into())
-        .collect();
-    Ok(result)
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, NaiveDateTime};
+use smart_default::SmartDefault;
+
+/// A filesystem dataset as listed by `zfs list`.
+#[derive(Debug, PartialEq, Eq)]
+pub(super) struct DatasetInfo {
+    pub(super) name: String,
+    /// The `mountpoint` property: a path, `legacy` or `none`.
+    pub(super) mountpoint: String,
+    /// `available` or `unavailable` for encrypted datasets, `-` otherwise.
+    pub(super) keystatus: String,
+    pub(super) encryption_root: String,
+}
+
+/// How to run `zfs`, possibly through a privilege wrapper like `sudo -n` or `doas`.
+#[derive(Debug, Clone, SmartDefault)]
+pub(crate) struct ZfsCommand {
+    /// The `zfs` executable, looked up in `PATH` unless it contains a slash.
+    #[default("zfs".into())]
+    pub(crate) binary: PathBuf,
+    /// The command and arguments to run `zfs` with, like `["sudo", "-n"]`.
+    pub(crate) prefix: Vec<String>,
 }
 
-/// Return zfs datasets as String in the form `<name>\t<mountpoint>\t<mounted>`.
-fn run_list_command() -> Result<String> {
-    let output = Command::new("zfs")
-        .args([
+impl ZfsCommand {
+    /// Use `zfs list` to get the names of the snapshots of the dataset in the order they were
+    /// taken, and whether data was written since the previous snapshot.
+    pub(super) fn get_written_snapshots(&self, dataset: &str) -> Result<Vec<(String, bool)>> {
+        Ok(self
+            .list_snapshots(dataset, "written")?
+            .into_iter()
+            .map(|(snapshot, written)| (snapshot, written != "0"))
+            .collect())
+    }
+
+    /// Use `zfs list` to get the GUIDs of the snapshots of the dataset, by snapshot name.
+    /// Snapshots replicated to another dataset keep their GUID.
+    pub(super) fn get_snapshot_guids(&self, dataset: &str) -> Result<HashMap<String, u64>> {
+        self.list_snapshot_numbers(dataset, "guid")
+    }
+
+    /// Use `zfs list` to get the transaction groups the snapshots of the dataset were created in,
+    /// by snapshot name. Unlike names, they tell which snapshots were taken before another one.
+    pub(super) fn get_snapshot_txgs(&self, dataset: &str) -> Result<HashMap<String, u64>> {
+        self.list_snapshot_numbers(dataset, "createtxg")
+    }
+
+    /// Use `zfs list` to get the creation times of the snapshots of the dataset in UTC, by
+    /// snapshot name.
+    pub(super) fn get_creation_times(
+        &self,
+        dataset: &str,
+    ) -> Result<HashMap<String, NaiveDateTime>> {
+        self.list_snapshot_numbers(dataset, "creation")?
+            .into_iter()
+            .map(|(snapshot, seconds)| {
+                let time = i64::try_from(seconds)
+                    .ok()
+                    .and_then(|s| DateTime::from_timestamp(s, 0))
+                    .with_context(|| format!("invalid creation time of snapshot {snapshot}"))?;
+                Ok((snapshot, time.naive_utc()))
+            })
+            .collect()
+    }
+
+    /// Use `zfs list` to get a numeric property of each snapshot of the dataset, by snapshot name.
+    fn list_snapshot_numbers(&self, dataset: &str, property: &str) -> Result<HashMap<String, u64>> {
+        self.list_snapshots(dataset, property)?
+            .into_iter()
+            .map(|(snapshot, value)| {
+                let value = value.parse().with_context(|| {
+                    format!("invalid {property} '{value}' of snapshot {snapshot}")
+                })?;
+                Ok((snapshot, value))
+            })
+            .collect()
+    }
+
+    /// Use `zfs list` to get the name and the value of a property of each snapshot of the
+    /// dataset.
+    fn list_snapshots(&self, dataset: &str, property: &str) -> Result<Vec<(String, String)>> {
+        let columns = format!("name,{property}");
+        // only snapshots of this dataset, without header, exact numbers
+        let string = self.run(&[
+            "list",
+            "-t",
+            "snapshot",
+            "-d",
+            "1",
+            "-H",
+            "-p",
+            "-s",
+            "createtxg",
+            "-o",
+            &columns,
+            dataset,
+        ])?;
+
+        string
+            .lines()
+            .map(|line| {
+                let (name, value) = line
+                    .split_once('\t')
+                    .with_context(|| format!("unexpected output of `zfs list`: '{line}'"))?;
+                let (_, snapshot) = name.split_once('@').with_context(|| {
+                    format!("`zfs list` returned '{name}', which is not a snapshot")
+                })?;
+                Ok((snapshot.to_owned(), value.to_owned()))
+            })
+            .collect()
+    }
+
+    /// Use `zfs list` to get the filesystem and all filesystems below it.
+    pub(super) fn list_filesystems(&self, dataset: &str) -> Result<Vec<DatasetInfo>> {
+        // without header, exact values
+        let string = self.run(&[
             "list",
-            "-t", // only datasets
+            "-t",
             "filesystem",
-            "-H", // no header
-            "-o", // only specific columns
-            "name,mountpoint,mounted",
-        ])
-        .output()
-        .context("could not get output of `zfs list`")?;
+            "-r",
+            "-H",
+            "-p",
+            "-o",
+            "name,mountpoint,keystatus,encryptionroot",
+            dataset,
+        ])?;
+
+        string
+            .lines()
+            .map(|line| {
+                let fields: Vec<_> = line.split('\t').collect();
+                let [name, mountpoint, keystatus, encryption_root] = fields[..] else {
+                    bail!("unexpected output of `zfs list`: '{line}'");
+                };
+                Ok(DatasetInfo {
+                    name: name.to_owned(),
+                    mountpoint: mountpoint.to_owned(),
+                    keystatus: keystatus.to_owned(),
+                    encryption_root: encryption_root.to_owned(),
+                })
+            })
+            .collect()
+    }
+
+    /// Use `zfs get` to get the snapshot the dataset was cloned from, if any.
+    pub(super) fn get_origin(&self, dataset: &str) -> Result<Option<String>> {
+        let origin = self.get_property(dataset, "origin")?;
+        Ok(Some(origin).filter(|o| o != "-"))
+    }
+
+    /// Use `zfs get` to get the value of a property of a dataset.
+    pub(super) fn get_property(&self, dataset: &str, property: &str) -> Result<String> {
+        // only the value, without header, exact numbers
+        let string = self.run(&["get", "-H", "-p", "-o", "value", property, dataset])?;
+        Ok(string.trim_end_matches('\n').to_owned())
+    }
+
+    /// Run `zfs` with the arguments, connected to the terminal so it can ask for a passphrase.
+    pub(super) fn run_interactive(&self, args: &[&str]) -> Result<()> {
+        let status = self
+            .command(args)
+            .status()
+            .with_context(|| format!("could not run `zfs {}`", args[0]))?;
+        if !status.success() {
+            bail!("`zfs {}` failed ({status})", args[0]);
+        }
+        Ok(())
+    }
+
+    /// Run `zfs` with the arguments and return its output. Fail if it does not exit successfully.
+    pub(super) fn run(&self, args: &[&str]) -> Result<String> {
+        let output = self
+            .command(args)
+            .output()
+            .with_context(|| format!("could not run `zfs {}`", args[0]))?;
+        if !output.status.success() {
+            bail!(
+                "`zfs {}` failed ({}): {}",
+                args[0],
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+
+        String::from_utf8(output.stdout)
+            .with_context(|| format!("`zfs {}` returned invalid UTF8", args[0]))
+    }
+
+    /// Unmount a filesystem with `umount`, run through the prefix like `zfs`.
+    pub(super) fn umount(&self, mount_point: &Path) -> Result<()> {
+        let status = self
+            .wrap("umount")
+            .arg(mount_point)
+            .status()
+            .context("could not run `umount`")?;
+        if !status.success() {
+            bail!("`umount` failed ({status})");
+        }
+        Ok(())
+    }
+
+    /// Whether commands are run through a privilege wrapper.
+    pub(super) fn has_prefix(&self) -> bool {
+        !self.prefix.is_empty()
+    }
+
+    fn command(&self, args: &[&str]) -> Command {
+        let mut command = self.wrap(&self.binary);
+        command.args(args);
+        command
+    }
+
+    /// Run the program through the prefix, if any.
+    fn wrap(&self, program: impl AsRef<OsStr>) -> Command {
+        match self.prefix.split_first() {
+            Some((wrapper, wrapper_args)) => {
+                let mut command = Command::new(wrapper);
+                command.args(wrapper_args).arg(program);
+                command
+            }
+            None => Command::new(program),
+        }
+    }
+}
+
+/// Write an executable shell script standing in for `zfs`.
+#[cfg(test)]
+pub(super) fn fake_zfs(dir: &Path, name: &str, script: &str) -> PathBuf {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = dir.join(name);
+    std::fs::write(&path, format!("#!/bin/sh\n{script}\n")).unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    path
+}
+
+#[cfg(test)]
+mod test {
+    use super::{fake_zfs, ZfsCommand};
+
+    #[test]
+    fn run_fake_zfs() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
 
-    let result = String::from_utf8(output.stdout).context("`zfs list` returned invalid UTF8")?;
+        let zfs = ZfsCommand {
+            binary: fake_zfs(
+                root,
+                "list",
+                r#"[ "$1 ${11} ${12}" = "list name,guid tank/home" ] || exit 2
+printf 'tank/home@a\t11\ntank/home@b\t12\n'"#,
+            ),
+            prefix: vec!["env".to_owned(), "LC_ALL=C".to_owned()],
+        };
+        let guids = zfs.get_snapshot_guids("tank/home").unwrap();
+        assert_eq!(guids.len(), 2);
+        assert_eq!(guids["b"], 12);
+        assert!(zfs.get_snapshot_guids("tank/other").is_err());
 
-    Ok(result)
+        let zfs = ZfsCommand {
+            binary: fake_zfs(root, "fail", "echo 'permission denied' >&2; exit 1"),
+            prefix: vec![],
+        };
+        let error = zfs.get_origin("tank/home").unwrap_err().to_string();
+        assert!(error.contains("exit status: 1"), "{error}");
+        assert!(error.contains("permission denied"), "{error}");
+    }
 }
@@ -1,52 +1,235 @@
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
 use std::path::PathBuf;
+use std::rc::Rc;
 
 use anyhow::{anyhow, bail};
 use anyhow::{Context, Result};
 use itertools::Itertools;
-use path_absolutize::Absolutize;
 
-use super::cmd::get_mountpoints_of_mounted_datasets;
+use super::cmd::ZfsCommand;
+use super::filter::SnapshotFilter;
+use super::naming::NamingSchemes;
+use super::provider::{SnapshotProvider, Volume};
+use super::remote::RemoteSnapshots;
+use super::scan::{Scan, ScanOptions};
 use super::snapshot::Snapshot;
+use super::version::{group_versions, Dedup, Version};
+use super::zfsprovider::{find_mount_of_dataset, read_dataset_snapshots};
+use crate::mode::Mode;
+use crate::revision::Revision;
 
 #[derive(Debug)]
 pub(crate) struct Dataset {
+    /// The name of the dataset, like `tank/home`.
+    pub(crate) name: String,
     pub(crate) path: PathBuf,
     snapshots: Vec<Snapshot>,
+    /// Resolves the path of a file inside the snapshots.
+    provider: Rc<dyn SnapshotProvider>,
+    dedup: Dedup,
+    bisect: bool,
+    scan_options: ScanOptions,
 }
 
 impl Dataset {
-    fn new(path: PathBuf) -> Result<Self> {
-        Ok(Self {
-            snapshots: Self::get_snapshots(path.clone())
-                .with_context(|| format!("could not get snapshots for dataset under {path:?}"))?,
-            path,
-        })
+    fn new(volume: Volume, snapshots: Vec<Snapshot>, provider: Rc<dyn SnapshotProvider>) -> Self {
+        Self {
+            name: volume.name,
+            path: volume.path,
+            snapshots,
+            provider,
+            dedup: Dedup::default(),
+            bisect: false,
+            scan_options: ScanOptions::default(),
+        }
+    }
+
+    /// Find the volume containing the path using the provider and return it with the path of the
+    /// file relative to the volume.
+    pub(crate) fn find(
+        path: &Path,
+        schemes: &NamingSchemes,
+        provider: &Rc<dyn SnapshotProvider>,
+    ) -> Result<(Self, PathBuf)> {
+        let (volume, live_path) = provider.find_volume(path)?;
+        let snapshots = provider.list_snapshots(&volume, schemes).with_context(|| {
+            format!(
+                "could not get snapshots for dataset under {:?}",
+                volume.path
+            )
+        })?;
+        let mut instance = Self::new(volume, snapshots, provider.clone());
+        instance.snapshots.sort_unstable();
+
+        let relative = instance.get_relative_path(&live_path)?;
+        Ok((instance, relative))
     }
 
-    /// Traverse the absolute path from the child to root, return the first zfs mountpoint and path
-    /// relative to the dataset.
-    pub(crate) fn find(path: &Path) -> Result<(Self, PathBuf)> {
-        let instance = Self::find_dataset(path)?;
-        let path = instance.get_relative_path(path)?;
-        Ok((instance, path))
+    /// Put the snapshots of the dataset this one was cloned from before its own snapshots, up to
+    /// and including the snapshot it was cloned from, as told by their `createtxg`. Repeat for the
+    /// origin of the origin. The file is at the same path relative to the mountpoint of the
+    /// origin.
+    pub(crate) fn add_origin_snapshots(
+        &mut self,
+        zfs: &ZfsCommand,
+        schemes: &NamingSchemes,
+    ) -> Result<()> {
+        let mut origin_snapshots = vec![];
+        let mut dataset = self.name.clone();
+        while let Some(origin) = zfs.get_origin(&dataset)? {
+            let (origin_dataset, clone_point) = origin
+                .split_once('@')
+                .with_context(|| format!("origin '{origin}' is not a snapshot"))?;
+            let mount = find_mount_of_dataset(zfs, origin_dataset)?;
+            let mut snapshots = read_dataset_snapshots(&mount.mount_point, schemes)?;
+            let txgs = zfs.get_snapshot_txgs(origin_dataset)?;
+            keep_up_to_clone_point(&mut snapshots, &txgs, clone_point)
+                .with_context(|| format!("snapshot {origin} not found"))?;
+            for snapshot in &mut snapshots {
+                snapshot.set_dataset(origin_dataset);
+            }
+
+            origin_snapshots.splice(0..0, snapshots);
+            dataset = origin_dataset.to_owned();
+        }
+
+        self.snapshots.splice(0..0, origin_snapshots);
+        Ok(())
     }
 
-    fn find_dataset(path: &Path) -> Result<Self> {
-        let filepath = path
-            .absolutize()
-            .with_context(|| format!("could not resolve filepath {path:?}"))?
-            .to_path_buf();
+    /// Add the snapshots of the replicas of the dataset, configured as `<dataset>=<replica>`. A
+    /// mapping applies to the children of the dataset, too. Snapshots that were replicated from the
+    /// dataset, which keep their GUID, are searched only once. The others are put into the
+    /// timeline by their creation time.
+    pub(crate) fn add_replica_snapshots(
+        &mut self,
+        zfs: &ZfsCommand,
+        replicas: &[String],
+        schemes: &NamingSchemes,
+    ) -> Result<()> {
+        let replicas: Vec<_> = replicas
+            .iter()
+            .filter_map(|r| r.split_once('='))
+            .filter_map(|(live, replica)| {
+                let child = self.name.strip_prefix(live)?;
+                (child.is_empty() || child.starts_with('/')).then(|| format!("{replica}{child}"))
+            })
+            .collect();
+        if replicas.is_empty() {
+            return Ok(());
+        }
 
-        let mounted_datasets = get_mountpoints_of_mounted_datasets()?;
+        let datasets: HashSet<_> = self
+            .snapshots
+            .iter()
+            .filter_map(|s| s.dataset())
+            .chain([self.name.as_str()])
+            .collect();
+        let mut known_guids = HashSet::new();
+        for dataset in datasets {
+            known_guids.extend(zfs.get_snapshot_guids(dataset)?.into_values());
+        }
+        self.read_creation_times(zfs)?;
 
-        for parent in filepath.ancestors() {
-            if is_zfs_dataset(parent, &mounted_datasets) {
-                return parent.to_owned().try_into();
+        for replica in replicas {
+            let mount = find_mount_of_dataset(zfs, &replica)?;
+            let guids = zfs.get_snapshot_guids(&replica)?;
+            let times = zfs.get_creation_times(&replica)?;
+            let mut snapshots = vec![];
+            for mut snapshot in read_dataset_snapshots(&mount.mount_point, schemes)? {
+                if let Some(guid) = guids.get(snapshot.name()) {
+                    if !known_guids.insert(*guid) {
+                        continue;
+                    }
+                }
+                if let Some(time) = times.get(snapshot.name()) {
+                    snapshot.set_created(*time);
+                }
+                snapshot.set_dataset(&replica);
+                snapshots.push(snapshot);
             }
+            snapshots.sort_by_key(Snapshot::taken);
+            self.merge_snapshots(snapshots);
+        }
+        Ok(())
+    }
+
+    /// Add the snapshots of the remote hosts configured for the file at `relative` to the timeline,
+    /// without fetching the file yet. Remote snapshots named like one of the dataset's own are
+    /// skipped, since they are taken to be replicas of it. Unlike for local replicas, only the
+    /// names are compared: remotes are reached through their `.zfs/snapshot` directory, and `zfs`
+    /// need not be usable there to tell the GUIDs.
+    pub(crate) fn add_remote_snapshots(
+        &mut self,
+        remotes: &RemoteSnapshots,
+        mappings: &[String],
+        relative: &Path,
+        schemes: &NamingSchemes,
+    ) -> Result<()> {
+        if mappings.is_empty() {
+            return Ok(());
+        }
+        let known = self
+            .snapshots
+            .iter()
+            .filter(|s| s.dataset().is_none())
+            .map(Snapshot::name)
+            .collect();
+        let path = self.get_absolute_path(relative);
+        let snapshots = remotes.list(mappings, &path, relative, &known, schemes)?;
+        self.merge_snapshots(snapshots);
+        Ok(())
+    }
+
+    /// Insert snapshots of other datasets into the timeline by the time they were taken. The
+    /// timeline keeps its order, which for origins follows `createtxg` rather than names. Each
+    /// snapshot goes after the last one taken no later than it, those without a known time go
+    /// first.
+    fn merge_snapshots(&mut self, snapshots: impl IntoIterator<Item = Snapshot>) {
+        let mut undated = 0;
+        for snapshot in snapshots {
+            let index = match snapshot.taken() {
+                Some(time) => self
+                    .snapshots
+                    .iter()
+                    .rposition(|s| s.taken().is_some_and(|t| t <= time))
+                    .map_or(undated, |i| i + 1),
+                None => {
+                    undated += 1;
+                    undated - 1
+                }
+            };
+            self.snapshots.insert(index, snapshot);
         }
+    }
 
-        bail!("file does not reside under any ZFS dataset")
+    /// Fetch the file from the remote snapshots that may be restored in the mode, after they
+    /// were filtered: the newest one containing it, the one the revision picks or, to compare
+    /// versions, all of them.
+    pub(crate) fn fetch_remote_files(&self, remotes: &RemoteSnapshots, mode: &Mode) -> Result<()> {
+        let mut with_file = self.snapshots.iter().filter(|s| remotes.has_file(s));
+        let needed: Vec<_> = match mode {
+            Mode::ChooseVersionInteractively | Mode::SpecificVersion(Revision::Nth(_)) => {
+                with_file.collect()
+            }
+            Mode::MostRecentVersion => with_file.next_back().into_iter().collect(),
+            Mode::SpecificVersion(Revision::Snapshot(name)) => {
+                with_file.filter(|s| s.name().starts_with(name)).collect()
+            }
+            Mode::SpecificVersion(Revision::Time(time)) => with_file
+                .rfind(|s| s.time().is_some_and(|t| t <= *time))
+                .into_iter()
+                .collect(),
+            // remote snapshots are matched by the time in their name
+            Mode::SpecificVersion(Revision::ExactTime { named, .. }) => {
+                with_file.filter(|s| s.time() == Some(*named)).collect()
+            }
+        };
+        for snapshot in needed {
+            remotes.fetch(snapshot)?;
+        }
+        Ok(())
     }
 
     pub(crate) fn get_relative_path(&self, path: &Path) -> Result<PathBuf> {
@@ -68,108 +251,463 @@ impl Dataset {
         &self.snapshots
     }
 
-    /// Get snapshots in alphabetically ascending order.
-    #[cfg(not(test))]
-    fn get_snapshots(mut path: PathBuf) -> Result<Vec<Snapshot>> {
-        let subdir = PathBuf::from(".zfs/snapshot");
-        path.push(subdir);
-        let mut errors = vec![];
-        let mut result: Vec<_> = path
-            .read_dir()
-            .with_context(|| format!("could not read zfs snapshot dir `{path:?}`"))?
-            .into_iter()
-            .filter_map(|r| r.map_err(|e| errors.push(e)).ok())
-            .map(|i| i.path())
-            .map(Snapshot::from)
-            .collect();
+    /// Drop all snapshots that do not pass the filter.
+    pub(crate) fn apply_filter(&mut self, filter: &SnapshotFilter) {
+        self.snapshots.retain(|s| filter.matches(s));
+    }
 
-        if !errors.is_empty() {
-            bail!("aggregation of snapshots failed, {:?}", errors);
+    /// Ask `zfs` for the creation times of the dataset's own snapshots.
+    pub(crate) fn read_creation_times(&mut self, zfs: &ZfsCommand) -> Result<()> {
+        let times = zfs.get_creation_times(&self.name)?;
+        for snapshot in self.snapshots.iter_mut().filter(|s| s.dataset().is_none()) {
+            if let Some(time) = times.get(snapshot.name()) {
+                snapshot.set_created(*time);
+            }
         }
-
-        result.sort_unstable();
-        Ok(result)
+        Ok(())
     }
 
-    /// Mock the function to enable tests on Dataset.
-    #[cfg(test)]
-    fn get_snapshots(_path: PathBuf) -> Result<Vec<Snapshot>> {
-        Ok(vec![])
+    /// Drop the dataset's own snapshots without changes compared to the previous one that is
+    /// kept, as told by `zfs`. They contain the same files as that snapshot, so probing them would
+    /// only mount them. Snapshots dropped before, e.g. by the filter, do not count as kept, so a
+    /// snapshot following a changed one that was dropped is kept. Origins and replicas may have
+    /// snapshots of the same name, which are kept.
+    pub(crate) fn skip_unchanged_snapshots(&mut self, zfs: &ZfsCommand) -> Result<()> {
+        let own: HashSet<&str> = self
+            .snapshots
+            .iter()
+            .filter(|s| s.dataset().is_none())
+            .map(Snapshot::name)
+            .collect();
+        let mut same_as_kept = false;
+        let mut unchanged = HashSet::new();
+        for (name, written) in zfs.get_written_snapshots(&self.name)? {
+            if !own.contains(name.as_str()) {
+                same_as_kept &= !written;
+            } else if !written && same_as_kept {
+                unchanged.insert(name);
+            } else {
+                same_as_kept = true;
+            }
+        }
+        self.snapshots
+            .retain(|s| s.dataset().is_some() || !unchanged.contains(s.name()));
+        Ok(())
     }
 
-    pub(crate) fn find_newest_snapshot_containing_the_file(&self, file: &Path) -> Result<PathBuf> {
+    pub(crate) fn find_newest_snapshot_containing_the_file(
+        &self,
+        file: &Path,
+    ) -> Result<&Snapshot> {
         assert!(!file.is_absolute(), "path must be relative, not absolute");
 
-        let full_path_in_snapshot = self
-            .snapshots()
+        let newest = if self.bisect {
+            self.bisect_newest_snapshot_containing_the_file(file)
+        } else {
+            let scan = Scan::run(&self.paths_in_snapshots(file), &self.scan_options, true);
+            scan.report(self.snapshots());
+            scan.infos
+                .iter()
+                .rposition(Option::is_some)
+                .map(|i| &self.snapshots[i])
+        };
+
+        newest.ok_or_else(|| anyhow!("file does not exist in any snapshot"))
+    }
+
+    /// Find the newest snapshot containing the file, assuming the file exists in a contiguous range
+    /// of snapshots. Probe backwards from the newest snapshot in exponentially growing steps until
+    /// a snapshot containing the file is found, then bisect between it and the last probe that did
+    /// not contain it. If no probe hits, fall back to a linear search.
+    fn bisect_newest_snapshot_containing_the_file(&self, file: &Path) -> Option<&Snapshot> {
+        let contains = |i: usize| self.contains_file(&self.snapshots[i], file);
+        let len = self.snapshots.len();
+
+        let mut without_file = len;
+        let mut step = 1;
+        let with_file = loop {
+            let probe = without_file.checked_sub(step)?;
+            if contains(probe) {
+                break probe;
+            }
+            if probe == 0 {
+                return self.snapshots[..len - 1]
+                    .iter()
+                    .rev()
+                    .find(|s| self.contains_file(s, file));
+            }
+            without_file = probe;
+            step = (step * 2).min(probe);
+        };
+
+        // invariant: `with_file` contains the file, `without_file` does not or is out of bounds
+        let (mut low, mut high) = (with_file, without_file);
+        while high - low > 1 {
+            let middle = low + (high - low) / 2;
+            if contains(middle) {
+                low = middle;
+            } else {
+                high = middle;
+            }
+        }
+        Some(&self.snapshots[low])
+    }
+
+    /// Find the snapshot selected by the revision. Fail if the file does not exist there.
+    pub(crate) fn resolve_revision(&self, file: &Path, revision: &Revision) -> Result<&Snapshot> {
+        let snapshot = match revision {
+            Revision::Snapshot(name) => self.find_snapshot_by_name(name)?,
+            Revision::Time(time) => self
+                .snapshots
+                .iter()
+                .rev()
+                .filter(|s| s.taken().is_some_and(|t| t <= *time))
+                .find(|s| self.contains_file(s, file))
+                .with_context(|| {
+                    format!("file does not exist in any snapshot taken at or before {time}")
+                })?,
+            Revision::ExactTime { created, named } => self
+                .snapshots
+                .iter()
+                .find(|s| s.created() == Some(*created))
+                .or_else(|| self.snapshots.iter().find(|s| s.time() == Some(*named)))
+                .with_context(|| format!("no snapshot taken at {created} UTC"))?,
+            Revision::Nth(n) => {
+                let versions = self.get_unique_versions(file)?;
+                match versions.get(*n) {
+                    Some(version) => version.snapshot(),
+                    None => bail!(
+                        "requested version ~{n}, but there are only {} unique versions (~0 to ~{})",
+                        versions.len(),
+                        versions.len() - 1
+                    ),
+                }
+            }
+        };
+
+        if !self.contains_file(snapshot, file) {
+            bail!("file does not exist in snapshot '{}'", snapshot.name());
+        }
+        Ok(snapshot)
+    }
+
+    /// Find the snapshot with exactly this name, or else the only snapshot whose name starts with
+    /// it.
+    fn find_snapshot_by_name(&self, name: &str) -> Result<&Snapshot> {
+        if let Some(snapshot) = self.snapshots.iter().find(|s| s.name() == name) {
+            return Ok(snapshot);
+        }
+
+        let candidates: Vec<_> = self
+            .snapshots
             .iter()
-            .rev()
-            .find_map(|snap| snap.contains_file(file))
-            .ok_or_else(|| anyhow!("file does not exist in any snapshot"))?;
-        Ok(full_path_in_snapshot)
+            .filter(|s| s.name().starts_with(name))
+            .collect();
+        match candidates.as_slice() {
+            [snapshot] => Ok(snapshot),
+            [] => {
+                bail!("no snapshot named '{name}' (use --literal if '@' is part of the filename)")
+            }
+            _ => bail!(
+                "snapshot name '{name}' is ambiguous, candidates are: {}",
+                candidates.iter().map(|s| s.name()).join(", ")
+            ),
+        }
+    }
+
+    /// Check if the target of the symlink `file` exists in the snapshot. Absolute targets inside
+    /// the dataset are looked up in the snapshot, too.
+    pub(crate) fn symlink_resolves(&self, snapshot: &Snapshot, file: &Path, target: &Path) -> bool {
+        let target = if target.is_relative() {
+            self.path_in_snapshot(snapshot, file)
+                .parent()
+                .map_or_else(|| target.to_path_buf(), |p| p.join(target))
+        } else if let Ok(relative) = target.strip_prefix(&self.path) {
+            self.path_in_snapshot(snapshot, relative)
+        } else {
+            target.to_path_buf()
+        };
+        target.exists()
     }
 
     pub(crate) fn get_absolute_path(&self, path: &Path) -> PathBuf {
         self.path.join(path)
     }
 
-    /// Get unique versions of the file using `st_mtime` and `st_size`. Output is sorted in reverse
-    /// alphabetical order.
-    pub(crate) fn get_unique_versions(&self, to_recover: &Path) -> Result<Vec<&Snapshot>> {
-        let result: Vec<_> = self
+    /// The path of a file, relative to the dataset, inside one of its snapshots, as resolved by
+    /// the provider.
+    pub(crate) fn path_in_snapshot(&self, snapshot: &Snapshot, file: &Path) -> PathBuf {
+        self.provider.resolve_path(snapshot, file)
+    }
+
+    /// The paths of a file inside all snapshots, in snapshot order.
+    fn paths_in_snapshots(&self, file: &Path) -> Vec<PathBuf> {
+        self.snapshots
+            .iter()
+            .map(|s| self.path_in_snapshot(s, file))
+            .collect()
+    }
+
+    /// Check if the snapshot contains the file. A dangling symlink counts, too.
+    fn contains_file(&self, snapshot: &Snapshot, file: &Path) -> bool {
+        self.path_in_snapshot(snapshot, file)
+            .symlink_metadata()
+            .is_ok()
+    }
+
+    /// Get unique versions of the file, compared as configured by `set_dedup`. Output is sorted
+    /// newest first.
+    pub(crate) fn get_unique_versions(&self, to_recover: &Path) -> Result<Vec<Version<'_>>> {
+        let paths = self.paths_in_snapshots(to_recover);
+        let scan = Scan::run(&paths, &self.scan_options, false);
+        scan.report(self.snapshots());
+        let infos: Vec<_> = self
             .snapshots
             .iter()
-            .filter_map(|s| {
-                s.get_file_information(to_recover)
-                    .ok()
-                    .map(|info| (s, info))
-            })
-            .unique_by(|(_, f)| (f.mtime, f.size))
-            .map(|(s, _)| s)
-            .rev()
+            .zip(paths)
+            .zip(scan.infos)
+            .filter_map(|((s, path), info)| Some((s, path, info?)))
             .collect();
 
-        if result.is_empty() {
+        if infos.is_empty() {
             bail!("file does not exist in any snapshot")
         }
 
+        let mut result = group_versions(infos, self.dedup)?;
+        result.reverse();
         Ok(result)
     }
-}
 
-/// check if a path is a zfs mountpoint
-fn is_zfs_dataset(path: &Path, datasets: &[PathBuf]) -> bool {
-    datasets.iter().any(|d| d == path)
-}
+    /// Set how to decide whether two snapshots contain the same version of a file.
+    pub(crate) fn set_dedup(&mut self, dedup: Dedup) {
+        self.dedup = dedup;
+    }
 
-impl TryFrom<PathBuf> for Dataset {
-    type Error = anyhow::Error;
+    /// Find the newest snapshot containing a file by bisection, which is only correct if the file
+    /// exists in a contiguous range of snapshots.
+    pub(crate) fn set_bisect(&mut self, bisect: bool) {
+        self.bisect = bisect;
+    }
 
-    fn try_from(path: PathBuf) -> Result<Self> {
-        Self::new(path)
+    /// Set how many snapshots are probed in parallel and for how long.
+    pub(crate) fn set_scan_options(&mut self, options: ScanOptions) {
+        self.scan_options = options;
     }
 }
 
+/// Keep the snapshots created up to and including the clone point, by their `createtxg`, in the
+/// order they were taken. Their names need not tell that order.
+fn keep_up_to_clone_point(
+    snapshots: &mut Vec<Snapshot>,
+    txgs: &HashMap<String, u64>,
+    clone_point: &str,
+) -> Option<()> {
+    let clone_txg = *txgs.get(clone_point)?;
+    snapshots.retain(|s| txgs.get(s.name()).is_some_and(|txg| *txg <= clone_txg));
+    snapshots.sort_by_key(|s| txgs[s.name()]);
+    Some(())
+}
+
 #[cfg(test)]
 mod test {
-    use super::Dataset;
-    use std::path::PathBuf;
+    use super::{keep_up_to_clone_point, Dataset};
+    use crate::revision::Revision;
+    use crate::zfs::cmd::{fake_zfs, ZfsCommand};
+    use crate::zfs::provider::Volume;
+    use crate::zfs::{DirectoryProvider, NamingSchemes, Snapshot};
+    use chrono::NaiveDateTime;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::rc::Rc;
+
+    fn dataset_at(path: &Path) -> Dataset {
+        let volume = Volume {
+            name: String::new(),
+            path: path.to_path_buf(),
+        };
+        let provider = DirectoryProvider::new(path.to_path_buf(), path.to_path_buf());
+        Dataset::new(volume, vec![], Rc::new(provider))
+    }
 
     #[test]
     fn make_path_relative() {
         let all = PathBuf::from("/a/b/c");
-        let dataset: Dataset = PathBuf::from("/a").try_into().unwrap();
+        let dataset = dataset_at(Path::new("/a"));
         let result = PathBuf::from("b/c");
         assert_eq!(dataset.get_relative_path(&all).unwrap(), result);
 
         let all = PathBuf::from("/a/b/c");
-        let dataset: Dataset = PathBuf::from("/").try_into().unwrap();
+        let dataset = dataset_at(Path::new("/"));
         let result = PathBuf::from("a/b/c");
         assert_eq!(dataset.get_relative_path(&all).unwrap(), result);
 
         let all = PathBuf::from("/a/b/c");
-        let dataset: Dataset = PathBuf::from("/a/b").try_into().unwrap();
+        let dataset = dataset_at(Path::new("/a/b"));
         let result = PathBuf::from("c");
         assert_eq!(dataset.get_relative_path(&all).unwrap(), result);
     }
+
+    #[test]
+    fn bisect_newest_snapshot() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let file = Path::new("file");
+        let schemes = NamingSchemes::default();
+
+        for (len, range) in [
+            (100, 30..70),
+            (100, 0..100),
+            (100, 99..100),
+            (7, 2..3),
+            (1, 0..1),
+        ] {
+            let mut dataset = dataset_at(root);
+            dataset.snapshots = (0..len)
+                .map(|i| {
+                    let dir = root.join(format!("{len}-{}-{i:03}", range.start));
+                    fs::create_dir_all(&dir).unwrap();
+                    if range.contains(&i) {
+                        fs::write(dir.join(file), "").unwrap();
+                    }
+                    Snapshot::new(dir, &schemes)
+                })
+                .collect();
+
+            let expected = dataset.snapshots[range.end - 1].path().to_path_buf();
+            for bisect in [false, true] {
+                dataset.set_bisect(bisect);
+                assert_eq!(
+                    dataset
+                        .find_newest_snapshot_containing_the_file(file)
+                        .unwrap()
+                        .path(),
+                    expected
+                );
+            }
+            assert!(dataset
+                .find_newest_snapshot_containing_the_file(Path::new("missing"))
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn resolve_gmt_tokens_by_creation_time() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let file = Path::new("file");
+        let time = |s| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S").unwrap();
+
+        let mut dataset = dataset_at(root);
+        for name in ["manual", "2022-11-15-073000"] {
+            fs::create_dir_all(root.join(name)).unwrap();
+            fs::write(root.join(name).join(file), "").unwrap();
+            let snapshot = Snapshot::new(root.join(name), &NamingSchemes::default());
+            dataset.snapshots.push(snapshot);
+        }
+        dataset.snapshots[0].set_created(time("2022-11-14 07:30:00"));
+
+        let revision = |created, named| Revision::ExactTime {
+            created: time(created),
+            named: time(named),
+        };
+        let resolve = |revision| {
+            dataset
+                .resolve_revision(file, &revision)
+                .map(Snapshot::name)
+        };
+        assert_eq!(
+            resolve(revision("2022-11-14 07:30:00", "2022-11-14 08:30:00")).unwrap(),
+            "manual"
+        );
+        assert_eq!(
+            resolve(revision("2022-11-15 06:30:00", "2022-11-15 07:30:00")).unwrap(),
+            "2022-11-15-073000"
+        );
+        assert!(resolve(revision("2022-11-16 07:30:00", "2022-11-16 07:30:00")).is_err());
+
+        // a name without a time is found by its creation time, which is UTC
+        assert_eq!(
+            resolve(Revision::Time(time("2022-11-14 23:00:00"))).unwrap(),
+            "manual"
+        );
+    }
+
+    #[test]
+    fn keep_origin_snapshots_up_to_the_clone_point() {
+        let schemes = NamingSchemes::default();
+        let names = ["2022-11-14-073000", "before", "clone", "zzz-after"];
+        let mut snapshots: Vec<_> = names
+            .iter()
+            .map(|name| Snapshot::new(PathBuf::from(name), &schemes))
+            .collect();
+        // a dated name sorts first, although it was taken after the clone point
+        let txgs = [
+            ("2022-11-14-073000", 40),
+            ("before", 10),
+            ("clone", 20),
+            ("zzz-after", 30),
+        ]
+        .map(|(name, txg)| (name.to_owned(), txg))
+        .into();
+
+        keep_up_to_clone_point(&mut snapshots, &txgs, "clone").unwrap();
+        let kept: Vec<_> = snapshots.iter().map(Snapshot::name).collect();
+        assert_eq!(kept, ["before", "clone"]);
+        assert!(keep_up_to_clone_point(&mut snapshots, &txgs, "missing").is_none());
+    }
+
+    #[test]
+    fn merge_snapshots_by_time() {
+        let schemes = NamingSchemes::default();
+        let snapshot = |name: &str| Snapshot::new(PathBuf::from(name), &schemes);
+        let mut dataset = dataset_at(Path::new("/tank/home"));
+        // an origin snapshot without a dated name, ordered by its `createtxg`
+        dataset.snapshots = ["zzz-origin", "2022-11-14-073000", "2022-11-16-073000"]
+            .map(snapshot)
+            .into();
+
+        dataset.merge_snapshots(["manual", "2022-11-15-073000", "2022-11-17-073000"].map(snapshot));
+        let names: Vec<_> = dataset.snapshots.iter().map(Snapshot::name).collect();
+        assert_eq!(
+            names,
+            [
+                "manual",
+                "zzz-origin",
+                "2022-11-14-073000",
+                "2022-11-15-073000",
+                "2022-11-16-073000",
+                "2022-11-17-073000"
+            ]
+        );
+    }
+
+    #[test]
+    fn skip_only_own_unchanged_snapshots() {
+        let dir = tempfile::tempdir().unwrap();
+        let zfs = ZfsCommand {
+            binary: fake_zfs(
+                dir.path(),
+                "zfs",
+                r"printf 'tank/home@a\t4096\ntank/home@b\t0\ntank/home@c\t8192\ntank/home@d\t0\ntank/home@e\t0\n'",
+            ),
+            prefix: vec![],
+        };
+        let schemes = NamingSchemes::default();
+        let mut dataset = dataset_at(dir.path());
+        dataset.name = "tank/home".to_owned();
+        let mut replica = Snapshot::new(PathBuf::from("/backup/home/.zfs/snapshot/a"), &schemes);
+        replica.set_dataset("backup/home");
+        // c was dropped by the filter, so d is the first to hold its files
+        let own = ["a", "b", "d", "e"]
+            .map(|name| Snapshot::new(Path::new("/tank/home/.zfs/snapshot").join(name), &schemes));
+        dataset.snapshots = [replica].into_iter().chain(own).collect();
+
+        dataset.skip_unchanged_snapshots(&zfs).unwrap();
+        let kept: Vec<_> = dataset
+            .snapshots
+            .iter()
+            .map(|s| (s.dataset(), s.name()))
+            .collect();
+        assert_eq!(kept, [(Some("backup/home"), "a"), (None, "a"), (None, "d")]);
+    }
 }
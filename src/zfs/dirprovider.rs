@@ -0,0 +1,204 @@
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Local};
+
+use super::naming::NamingSchemes;
+use super::provider::{read_snapshot_dir, SnapshotProvider, Volume};
+use super::snapshot::Snapshot;
+
+/// Snapshots kept as plain directories, like those of rsnapshot or dated backup folders: the live
+/// files are below `root`, each snapshot is a directory in `snapshot_dir` containing a copy of
+/// `root` at `inner`.
+#[derive(Debug)]
+pub(crate) struct DirectoryProvider {
+    root: PathBuf,
+    snapshot_dir: PathBuf,
+    inner: PathBuf,
+}
+
+impl DirectoryProvider {
+    pub(crate) fn new(root: PathBuf, snapshot_dir: PathBuf) -> Self {
+        Self {
+            root,
+            snapshot_dir,
+            inner: PathBuf::new(),
+        }
+    }
+
+    /// Parse a mapping like `/home=/mnt/backup` or `/home=/mnt/backup/*/localhost/home`, where
+    /// `*` stands for the directory of each snapshot and defaults to the end of the path.
+    pub(crate) fn parse(mapping: &str) -> Result<Self> {
+        let (root, backup) = mapping
+            .split_once('=')
+            .with_context(|| format!("directory snapshots '{mapping}' are not <path>=<backup>"))?;
+        let backup = Path::new(backup);
+        let mut snapshot_dir = PathBuf::new();
+        let mut inner = PathBuf::new();
+        let mut found_placeholder = false;
+        for component in backup.components() {
+            if component == Component::Normal("*".as_ref()) {
+                if found_placeholder {
+                    bail!("directory snapshots '{mapping}' contain more than one '*'");
+                }
+                found_placeholder = true;
+            } else if found_placeholder {
+                inner.push(component);
+            } else {
+                snapshot_dir.push(component);
+            }
+        }
+        if !Path::new(root).is_absolute() || !snapshot_dir.is_absolute() {
+            bail!("directory snapshots '{mapping}' must map an absolute path to an absolute path");
+        }
+
+        Ok(Self {
+            root: root.into(),
+            snapshot_dir,
+            inner,
+        })
+    }
+
+    /// Whether the absolute path is below `root` or inside one of the snapshots.
+    pub(crate) fn contains(&self, path: &Path) -> bool {
+        path.starts_with(&self.root) || path.starts_with(&self.snapshot_dir)
+    }
+
+    /// How many components of the path are matched, to prefer nested mappings.
+    pub(crate) fn specificity(&self, path: &Path) -> usize {
+        [&self.root, &self.snapshot_dir]
+            .into_iter()
+            .filter(|prefix| path.starts_with(prefix))
+            .map(|prefix| prefix.components().count())
+            .max()
+            .unwrap_or_default()
+    }
+
+    fn volume(&self) -> Volume {
+        Volume {
+            name: self.root.display().to_string(),
+            path: self.root.clone(),
+        }
+    }
+}
+
+impl SnapshotProvider for DirectoryProvider {
+    /// A path inside a snapshot is mapped to the same path below `root`.
+    fn find_volume(&self, path: &Path) -> Result<(Volume, PathBuf)> {
+        if let Ok(in_snapshot_dir) = path.strip_prefix(&self.snapshot_dir) {
+            let mut components = in_snapshot_dir.iter();
+            components
+                .next()
+                .context("path points to the snapshot directory itself")?;
+            let in_snapshot: PathBuf = components.collect();
+            let relative = in_snapshot.strip_prefix(&self.inner).with_context(|| {
+                format!(
+                    "path is not inside the copy of {:?} in the snapshot",
+                    self.root
+                )
+            })?;
+            if relative.as_os_str().is_empty() {
+                bail!("path points to the snapshot itself, not to a file inside of it");
+            }
+            return Ok((self.volume(), self.root.join(relative)));
+        }
+
+        if !path.starts_with(&self.root) {
+            bail!("file does not reside under {:?}", self.root);
+        }
+        Ok((self.volume(), path.to_path_buf()))
+    }
+
+    /// Snapshots whose name encodes no time, like rsnapshot's `daily.0`, are dated by the
+    /// modification time of their directory, and the part before the dot is their class. Those
+    /// whose time cannot be read are skipped with a warning.
+    fn list_snapshots(&self, _volume: &Volume, schemes: &NamingSchemes) -> Result<Vec<Snapshot>> {
+        Ok(read_snapshot_dir(&self.snapshot_dir, schemes)?
+            .into_iter()
+            .filter(|snapshot| snapshot.path().is_dir())
+            .filter_map(|snapshot| {
+                let path = snapshot.join(&self.inner);
+                let name = snapshot.name().to_owned();
+                if let Some(time) = snapshot.time() {
+                    let class = snapshot.class().map(str::to_owned);
+                    return Some(Snapshot::with_details(path, name, Some(time), class, None));
+                }
+
+                let modified = match snapshot.path().metadata().and_then(|m| m.modified()) {
+                    Ok(modified) => modified,
+                    Err(e) => {
+                        eprintln!("skipping {snapshot}, could not read its time: {e}");
+                        return None;
+                    }
+                };
+                let time = DateTime::<Local>::from(modified).naive_local();
+                let class = name
+                    .split_once('.')
+                    .filter(|(_, number)| number.parse::<u32>().is_ok())
+                    .map(|(class, _)| class.to_owned());
+                Some(Snapshot::with_details(path, name, Some(time), class, None))
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    use super::DirectoryProvider;
+    use crate::zfs::provider::SnapshotProvider;
+    use crate::zfs::NamingSchemes;
+
+    #[test]
+    fn parse_mappings() {
+        let provider = DirectoryProvider::parse("/home=/mnt/backup/*/localhost/home").unwrap();
+        assert_eq!(provider.root, PathBuf::from("/home"));
+        assert_eq!(provider.snapshot_dir, PathBuf::from("/mnt/backup"));
+        assert_eq!(provider.inner, PathBuf::from("localhost/home"));
+        assert!(provider.contains(Path::new("/mnt/backup/daily.0/localhost/home/a")));
+
+        let (_, live_path) = provider
+            .find_volume(Path::new("/mnt/backup/daily.0/localhost/home/penguin/a"))
+            .unwrap();
+        assert_eq!(live_path, PathBuf::from("/home/penguin/a"));
+        assert!(provider
+            .find_volume(Path::new("/mnt/backup/daily.0/localhost/etc/a"))
+            .is_err());
+
+        let provider = DirectoryProvider::parse("/srv=/mnt/dated").unwrap();
+        assert_eq!(provider.snapshot_dir, PathBuf::from("/mnt/dated"));
+        assert_eq!(provider.inner, PathBuf::new());
+
+        assert!(DirectoryProvider::parse("/srv").is_err());
+        assert!(DirectoryProvider::parse("srv=/mnt/dated").is_err());
+        assert!(DirectoryProvider::parse("/srv=/mnt/*/x/*").is_err());
+    }
+
+    #[test]
+    fn list_rsnapshot_and_dated_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        for name in ["daily.0", "daily.1", "2022-11-14"] {
+            fs::create_dir_all(root.join(name).join("localhost")).unwrap();
+        }
+        fs::write(root.join("rsnapshot.log"), "").unwrap();
+
+        let provider =
+            DirectoryProvider::parse(&format!("/home={}/*/localhost", root.display())).unwrap();
+        let volume = provider.find_volume(Path::new("/home/a")).unwrap().0;
+        let mut snapshots = provider
+            .list_snapshots(&volume, &NamingSchemes::default())
+            .unwrap();
+        snapshots.sort_unstable();
+
+        assert_eq!(snapshots.len(), 3);
+        assert_eq!(snapshots[0].name(), "2022-11-14");
+        assert_eq!(snapshots[0].class(), None);
+        assert!(snapshots.iter().all(|s| s.time().is_some()));
+        let daily = snapshots.iter().find(|s| s.name() == "daily.1").unwrap();
+        assert_eq!(daily.class(), Some("daily"));
+        assert_eq!(daily.path(), root.join("daily.1/localhost"));
+    }
+}
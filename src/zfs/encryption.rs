@@ -0,0 +1,121 @@
+use std::error::Error;
+use std::fmt::Display;
+
+use anyhow::Result;
+
+use super::cmd::ZfsCommand;
+use crate::ui::user_agrees;
+
+/// The key of an encrypted dataset is not loaded, so it cannot be mounted and its snapshots cannot
+/// be read.
+#[derive(Debug)]
+pub(crate) struct KeyNotLoaded {
+    pub(crate) dataset: String,
+    /// The dataset the key belongs to, which may be a parent of the dataset.
+    pub(crate) encryption_root: String,
+}
+
+impl Display for KeyNotLoaded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "dataset {} is encrypted and its key is not loaded, run `zfs load-key {}` and mount it",
+            self.dataset, self.encryption_root
+        )
+    }
+}
+
+impl Error for KeyNotLoaded {}
+
+/// Fail with `KeyNotLoaded` if the dataset is encrypted and its key is not loaded.
+pub(super) fn check_key(zfs: &ZfsCommand, dataset: &str) -> Result<()> {
+    // `-` for datasets that are not encrypted
+    if zfs.get_property(dataset, "keystatus")? == "unavailable" {
+        return Err(KeyNotLoaded {
+            dataset: dataset.to_owned(),
+            encryption_root: zfs.get_property(dataset, "encryptionroot")?,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// The keys loaded and the datasets mounted on behalf of the user. If `unload` is set, this is
+/// undone when dropped, so it happens on every way out.
+#[derive(Debug)]
+pub(crate) struct LoadedKeys {
+    zfs: ZfsCommand,
+    unload: bool,
+    encryption_roots: Vec<String>,
+    datasets: Vec<String>,
+}
+
+impl LoadedKeys {
+    pub(crate) fn new(zfs: ZfsCommand, unload: bool) -> Self {
+        Self {
+            zfs,
+            unload,
+            encryption_roots: vec![],
+            datasets: vec![],
+        }
+    }
+
+    /// Run `f`. If it fails because a key is not loaded, offer to load the key and mount the
+    /// dataset, then try again. Only ask if `may_load` is set.
+    pub(crate) fn retry<T>(
+        &mut self,
+        may_load: bool,
+        mut f: impl FnMut() -> Result<T>,
+    ) -> Result<T> {
+        loop {
+            let error = match f() {
+                Ok(result) => return Ok(result),
+                Err(e) => e,
+            };
+            let Some(locked) = error.downcast_ref::<KeyNotLoaded>() else {
+                return Err(error);
+            };
+            // do not ask again for a key that did not help
+            if !may_load || self.encryption_roots.contains(&locked.encryption_root) {
+                return Err(error);
+            }
+
+            eprintln!("{locked}");
+            let question = format!("Load the key of {}?", locked.encryption_root);
+            if !user_agrees(&question)? {
+                return Err(error);
+            }
+            self.load(locked)?;
+        }
+    }
+
+    fn load(&mut self, locked: &KeyNotLoaded) -> Result<()> {
+        self.zfs
+            .run_interactive(&["load-key", &locked.encryption_root])?;
+        self.encryption_roots.push(locked.encryption_root.clone());
+        self.zfs.run(&["mount", &locked.dataset])?;
+        self.datasets.push(locked.dataset.clone());
+        Ok(())
+    }
+
+    /// Unmount the datasets and unload the keys again.
+    fn unload(&self) -> Result<()> {
+        for dataset in self.datasets.iter().rev() {
+            self.zfs.run(&["unmount", dataset])?;
+        }
+        for encryption_root in self.encryption_roots.iter().rev() {
+            self.zfs.run(&["unload-key", encryption_root])?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for LoadedKeys {
+    fn drop(&mut self) {
+        if self.unload {
+            if let Err(e) = self.unload() {
+                eprintln!("could not unload keys: {e:#}");
+            }
+        }
+    }
+}
@@ -1,17 +1,232 @@
-use std::fs::Metadata;
-use std::time::SystemTime;
+use std::ffi::{CString, OsString};
+use std::fmt::Display;
+use std::fs::{self, Metadata};
+use std::io;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-#[derive(Debug)]
-pub(in crate::zfs) struct FileInfo {
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Local};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum FileType {
+    Regular,
+    Directory,
+    Symlink,
+    Fifo,
+    Socket,
+    BlockDevice,
+    CharDevice,
+}
+
+impl From<fs::FileType> for FileType {
+    fn from(t: fs::FileType) -> Self {
+        if t.is_dir() {
+            Self::Directory
+        } else if t.is_symlink() {
+            Self::Symlink
+        } else if t.is_fifo() {
+            Self::Fifo
+        } else if t.is_socket() {
+            Self::Socket
+        } else if t.is_block_device() {
+            Self::BlockDevice
+        } else if t.is_char_device() {
+            Self::CharDevice
+        } else {
+            Self::Regular
+        }
+    }
+}
+
+impl Display for FileType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Regular => "file",
+            Self::Directory => "directory",
+            Self::Symlink => "symlink",
+            Self::Fifo => "fifo",
+            Self::Socket => "socket",
+            Self::BlockDevice => "block device",
+            Self::CharDevice => "character device",
+        })
+    }
+}
+
+/// The metadata of a file in a snapshot. Symlinks are not followed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct FileInfo {
+    pub file_type: FileType,
+    /// The permission bits, including setuid, setgid and sticky bit.
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub inode: u64,
+    pub nlink: u64,
     pub mtime: SystemTime,
+    pub ctime: SystemTime,
     pub size: usize,
+    pub xattr_names: Vec<OsString>,
+    pub symlink_target: Option<PathBuf>,
+}
+
+impl FileInfo {
+    /// Read the metadata of the file at `path`.
+    pub(crate) fn read(path: &Path) -> Result<Self> {
+        let metadata = fs::symlink_metadata(path)
+            .with_context(|| format!("could not read metadata of {path:?}"))?;
+        Self::new(path, metadata)
+    }
+
+    /// Complete the metadata of the file at `path` with its extended attributes and symlink
+    /// target.
+    pub(crate) fn new(path: &Path, metadata: Metadata) -> Result<Self> {
+        let mut result: Self = metadata
+            .try_into()
+            .with_context(|| format!("incomplete metadata of {path:?}"))?;
+
+        if result.file_type == FileType::Symlink {
+            result.symlink_target = Some(
+                fs::read_link(path)
+                    .with_context(|| format!("could not read symlink target of {path:?}"))?,
+            );
+        }
+        result.xattr_names = list_xattr_names(path)
+            .with_context(|| format!("could not list extended attributes of {path:?}"))?;
+
+        Ok(result)
+    }
+
+    /// Summarize the metadata `ls -l` does not show: the type, owner, inode, change time and
+    /// extended attributes.
+    pub(crate) fn summary(&self) -> String {
+        let ctime = DateTime::<Local>::from(self.ctime).format("%Y-%m-%d %H:%M:%S");
+        let mut result = format!(
+            "{}, owner {}:{}, inode {}, changed {ctime}",
+            self.file_type, self.uid, self.gid, self.inode
+        );
+        if !self.xattr_names.is_empty() {
+            let names: Vec<_> = self
+                .xattr_names
+                .iter()
+                .map(|n| n.to_string_lossy())
+                .collect();
+            result.push_str(&format!(", xattrs {}", names.join(" ")));
+        }
+        result
+    }
+}
+
+impl TryFrom<Metadata> for FileInfo {
+    type Error = anyhow::Error;
+
+    /// Convert everything but the extended attributes and the symlink target, which are not part
+    /// of `Metadata`.
+    fn try_from(m: Metadata) -> Result<Self> {
+        Ok(Self {
+            file_type: m.file_type().into(),
+            mode: m.mode() & 0o7777,
+            uid: m.uid(),
+            gid: m.gid(),
+            inode: m.ino(),
+            nlink: m.nlink(),
+            mtime: m.modified().context("mtime is not available")?,
+            ctime: system_time(m.ctime(), m.ctime_nsec()).context("invalid ctime")?,
+            size: m
+                .len()
+                .try_into()
+                .context("file size does not fit into usize")?,
+            xattr_names: vec![],
+            symlink_target: None,
+        })
+    }
 }
 
-impl From<Metadata> for FileInfo {
-    fn from(m: Metadata) -> Self {
-        Self {
-            mtime: m.modified().expect("should work on Linux"),
-            size: m.len() as usize,
+fn system_time(secs: i64, nsecs: i64) -> Result<SystemTime> {
+    let nsecs = Duration::from_nanos(nsecs.try_into()?);
+    let result = if secs >= 0 {
+        UNIX_EPOCH + Duration::from_secs(secs as u64) + nsecs
+    } else {
+        UNIX_EPOCH - Duration::from_secs(secs.unsigned_abs()) + nsecs
+    };
+    Ok(result)
+}
+
+/// List the names of the extended attributes of a file, without following symlinks.
+fn list_xattr_names(path: &Path) -> Result<Vec<OsString>> {
+    let path = CString::new(path.as_os_str().as_bytes())?;
+
+    let buffer = loop {
+        // SAFETY: `path` is a valid C string, a null buffer of size 0 queries the required size
+        let size = unsafe { libc::llistxattr(path.as_ptr(), std::ptr::null_mut(), 0) };
+        if size < 0 {
+            let error = io::Error::last_os_error();
+            if error.raw_os_error() == Some(libc::ENOTSUP) {
+                return Ok(vec![]);
+            }
+            bail!(error);
+        }
+
+        let mut buffer = vec![0u8; size as usize];
+        // SAFETY: `buffer` is valid for `buffer.len()` bytes
+        let length =
+            unsafe { libc::llistxattr(path.as_ptr(), buffer.as_mut_ptr().cast(), buffer.len()) };
+        if length >= 0 {
+            buffer.truncate(length as usize);
+            break buffer;
         }
+
+        let error = io::Error::last_os_error();
+        // the list grew in between, try again
+        if error.raw_os_error() != Some(libc::ERANGE) {
+            bail!(error);
+        }
+    };
+
+    Ok(buffer
+        .split(|b| *b == 0)
+        .filter(|name| !name.is_empty())
+        .map(|name| OsString::from_vec(name.to_vec()))
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use std::os::unix::fs::symlink;
+    use std::path::Path;
+    use std::process::Command;
+
+    use super::{FileInfo, FileType};
+
+    #[test]
+    fn read_symlinks_and_special_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        symlink("does-not-exist", root.join("dangling")).unwrap();
+        Command::new("mkfifo")
+            .arg(root.join("fifo"))
+            .status()
+            .unwrap();
+
+        let info = FileInfo::read(&root.join("dangling")).unwrap();
+        assert_eq!(info.file_type, FileType::Symlink);
+        assert_eq!(
+            info.symlink_target.as_deref(),
+            Some(Path::new("does-not-exist"))
+        );
+
+        let info = FileInfo::read(&root.join("fifo")).unwrap();
+        assert_eq!(info.file_type, FileType::Fifo);
+        assert_eq!(info.symlink_target, None);
+        let summary = info.summary();
+        assert!(summary.starts_with("fifo, owner "), "{summary}");
+        assert!(
+            summary.contains(&format!(", inode {}, ", info.inode)),
+            "{summary}"
+        );
+
+        assert!(FileInfo::read(&root.join("missing")).is_err());
     }
 }
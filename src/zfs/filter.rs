@@ -0,0 +1,85 @@
+use regex::Regex;
+
+use super::snapshot::Snapshot;
+
+/// Restricts which snapshots are considered, by class or by name.
+#[derive(Debug, Default)]
+pub(crate) struct SnapshotFilter {
+    /// Only consider snapshots of these classes. Empty means all classes.
+    pub(crate) only_classes: Vec<String>,
+    /// Never consider snapshots of these classes.
+    pub(crate) exclude_classes: Vec<String>,
+    /// Only consider snapshots whose name matches.
+    pub(crate) include: Option<Regex>,
+    /// Never consider snapshots whose name matches.
+    pub(crate) exclude: Option<Regex>,
+}
+
+impl SnapshotFilter {
+    /// Check if the snapshot passes all restrictions.
+    pub(crate) fn matches(&self, snapshot: &Snapshot) -> bool {
+        let class = snapshot.class();
+        let name = snapshot.name();
+
+        if !self.only_classes.is_empty()
+            && !class.is_some_and(|c| self.only_classes.iter().any(|o| o == c))
+        {
+            return false;
+        }
+        if class.is_some_and(|c| self.exclude_classes.iter().any(|e| e == c)) {
+            return false;
+        }
+        if self.include.as_ref().is_some_and(|r| !r.is_match(name)) {
+            return false;
+        }
+        if self.exclude.as_ref().is_some_and(|r| r.is_match(name)) {
+            return false;
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use regex::Regex;
+
+    use super::SnapshotFilter;
+    use crate::zfs::naming::NamingSchemes;
+    use crate::zfs::Snapshot;
+
+    fn snapshot(name: &str) -> Snapshot {
+        let path = PathBuf::from("/tank/.zfs/snapshot").join(name);
+        Snapshot::new(path, &NamingSchemes::default())
+    }
+
+    #[test]
+    fn filter_by_class_and_name() {
+        let hourly = snapshot("autosnap_2022-11-14_07:00:00_hourly");
+        let daily = snapshot("autosnap_2022-11-14_00:00:00_daily");
+        let manual = snapshot("before-upgrade");
+
+        let filter = SnapshotFilter::default();
+        assert!(filter.matches(&hourly) && filter.matches(&daily) && filter.matches(&manual));
+
+        let filter = SnapshotFilter {
+            only_classes: vec!["daily".to_owned(), "weekly".to_owned()],
+            ..Default::default()
+        };
+        assert!(!filter.matches(&hourly) && filter.matches(&daily) && !filter.matches(&manual));
+
+        let filter = SnapshotFilter {
+            exclude_classes: vec!["hourly".to_owned()],
+            ..Default::default()
+        };
+        assert!(!filter.matches(&hourly) && filter.matches(&daily) && filter.matches(&manual));
+
+        let filter = SnapshotFilter {
+            include: Some(Regex::new("^autosnap_").unwrap()),
+            exclude: Some(Regex::new("_00:00:00_").unwrap()),
+            ..Default::default()
+        };
+        assert!(filter.matches(&hourly) && !filter.matches(&daily) && !filter.matches(&manual));
+    }
+}
@@ -0,0 +1,218 @@
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fmt::Display;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+
+use super::fileinfo::{FileInfo, FileType};
+use super::version::hash_file;
+
+/// The state of a single entry below a directory. Properties that change whenever a file is
+/// copied, like the inode number and `st_ctime`, are left out.
+#[derive(Debug, PartialEq, Eq)]
+struct Entry {
+    file_type: FileType,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    size: usize,
+    mtime: SystemTime,
+    xattr_names: Vec<OsString>,
+    symlink_target: Option<PathBuf>,
+    hash: Option<[u8; 32]>,
+}
+
+impl Entry {
+    /// Feed every field into the hasher.
+    fn hash_into(&self, hasher: &mut Sha256) {
+        hasher.update([self.file_type as u8]);
+        for number in [self.mode, self.uid, self.gid] {
+            hasher.update(number.to_le_bytes());
+        }
+        hasher.update((self.size as u64).to_le_bytes());
+        let (before_epoch, since_epoch) = match self.mtime.duration_since(UNIX_EPOCH) {
+            Ok(duration) => (false, duration),
+            Err(e) => (true, e.duration()),
+        };
+        hasher.update([u8::from(before_epoch)]);
+        hasher.update(since_epoch.as_secs().to_le_bytes());
+        hasher.update(since_epoch.subsec_nanos().to_le_bytes());
+        hasher.update((self.xattr_names.len() as u64).to_le_bytes());
+        for name in &self.xattr_names {
+            hasher.update(name.as_bytes());
+            hasher.update([0]);
+        }
+        match &self.symlink_target {
+            Some(target) => {
+                hasher.update([1]);
+                hasher.update(target.as_os_str().as_bytes());
+                hasher.update([0]);
+            }
+            None => hasher.update([0]),
+        }
+        match &self.hash {
+            Some(hash) => {
+                hasher.update([1]);
+                hasher.update(hash);
+            }
+            None => hasher.update([0]),
+        }
+    }
+}
+
+/// The names, metadata and optionally the content hashes of everything below a directory.
+#[derive(Debug)]
+pub(crate) struct Fingerprint {
+    entries: BTreeMap<PathBuf, Entry>,
+}
+
+impl Fingerprint {
+    /// Walk the directory recursively. Symlinks are not followed.
+    pub(super) fn of_directory(dir: &Path, with_hashes: bool) -> Result<Self> {
+        let mut entries = BTreeMap::new();
+        let mut to_visit = vec![PathBuf::new()];
+
+        while let Some(relative) = to_visit.pop() {
+            let absolute = dir.join(&relative);
+            let read_dir = absolute
+                .read_dir()
+                .with_context(|| format!("could not read directory {absolute:?}"))?;
+            for entry in read_dir {
+                let entry = entry.with_context(|| format!("could not read {absolute:?}"))?;
+                let info = FileInfo::read(&entry.path())?;
+                let path = relative.join(entry.file_name());
+                let is_dir = info.file_type == FileType::Directory;
+                let hash = if with_hashes && info.file_type == FileType::Regular {
+                    Some(hash_file(&entry.path())?)
+                } else {
+                    None
+                };
+
+                if is_dir {
+                    to_visit.push(path.clone());
+                }
+                entries.insert(
+                    path,
+                    Entry {
+                        file_type: info.file_type,
+                        mode: info.mode,
+                        uid: info.uid,
+                        gid: info.gid,
+                        // the size and time of a directory change along with its entries
+                        size: if is_dir { 0 } else { info.size },
+                        mtime: if is_dir { UNIX_EPOCH } else { info.mtime },
+                        xattr_names: info.xattr_names,
+                        symlink_target: info.symlink_target,
+                        hash,
+                    },
+                );
+            }
+        }
+
+        Ok(Self { entries })
+    }
+
+    /// A hash over all entries, which is equal for equal fingerprints.
+    pub(super) fn digest(&self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        for (path, entry) in &self.entries {
+            // paths and attribute names cannot contain a NUL byte, so it ends them unambiguously
+            hasher.update(path.as_os_str().as_bytes());
+            hasher.update([0]);
+            entry.hash_into(&mut hasher);
+        }
+        hasher.finalize().into()
+    }
+
+    /// Count the entries that were added, removed and modified compared to an older fingerprint.
+    pub(super) fn changes_since(&self, older: &Self) -> Changes {
+        let mut changes = Changes::default();
+        for (path, entry) in &self.entries {
+            match older.entries.get(path) {
+                None => changes.added += 1,
+                Some(old) if old != entry => changes.modified += 1,
+                Some(_) => (),
+            }
+        }
+        changes.removed = older
+            .entries
+            .keys()
+            .filter(|path| !self.entries.contains_key(*path))
+            .count();
+        changes
+    }
+}
+
+/// The changes of a directory between two versions.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct Changes {
+    pub(crate) added: usize,
+    pub(crate) removed: usize,
+    pub(crate) modified: usize,
+}
+
+impl Display for Changes {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} added, {} removed, {} modified",
+            self.added, self.removed, self.modified
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::{self, File};
+    use std::time::SystemTime;
+
+    use super::{Changes, Fingerprint};
+
+    #[test]
+    fn compare_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let (old, new) = (root.join("old"), root.join("new"));
+        for dir in [&old, &new] {
+            fs::create_dir_all(dir.join("sub")).unwrap();
+            fs::write(dir.join("same"), "same").unwrap();
+            File::options()
+                .write(true)
+                .open(dir.join("same"))
+                .unwrap()
+                .set_modified(SystemTime::UNIX_EPOCH)
+                .unwrap();
+        }
+        fs::write(old.join("removed"), "").unwrap();
+        fs::write(old.join("sub/changed"), "a").unwrap();
+        fs::write(new.join("sub/changed"), "bb").unwrap();
+        fs::write(new.join("sub/added"), "").unwrap();
+        let copy = root.join("copy");
+        fs::create_dir_all(&copy).unwrap();
+        std::process::Command::new("cp")
+            .args(["-a"])
+            .arg(old.join("."))
+            .arg(&copy)
+            .status()
+            .unwrap();
+
+        let old_fingerprint = Fingerprint::of_directory(&old, true).unwrap();
+        let new_fingerprint = Fingerprint::of_directory(&new, true).unwrap();
+        let copy_fingerprint = Fingerprint::of_directory(&copy, true).unwrap();
+
+        assert_eq!(old_fingerprint.digest(), copy_fingerprint.digest());
+        assert_ne!(old_fingerprint.digest(), new_fingerprint.digest());
+        assert_eq!(
+            new_fingerprint.changes_since(&old_fingerprint),
+            Changes {
+                added: 1,
+                removed: 1,
+                modified: 1
+            }
+        );
+    }
+}
@@ -1,7 +1,38 @@
+mod automount;
 mod cmd;
 mod dataset;
+mod dirprovider;
+mod encryption;
 mod fileinfo;
+mod filter;
+mod fingerprint;
+mod mountinfo;
+mod naming;
+mod provider;
+mod remote;
+mod scan;
+mod sendstream;
+mod snapper;
 mod snapshot;
+mod snapshotpath;
+mod staging;
+mod streamprovider;
+mod version;
+mod zap;
+mod zfsprovider;
+mod zpl;
 
+pub(crate) use automount::Automounts;
+pub(crate) use cmd::ZfsCommand;
 pub(crate) use dataset::Dataset;
+pub(crate) use dirprovider::DirectoryProvider;
+pub(crate) use encryption::LoadedKeys;
+pub(crate) use filter::SnapshotFilter;
+pub(crate) use mountinfo::Mount;
+pub(crate) use naming::NamingSchemes;
+pub(crate) use provider::{provider_for, SnapshotProvider};
+pub(crate) use remote::{RemoteCommand, RemoteSnapshots};
+pub(crate) use scan::ScanOptions;
 pub(crate) use snapshot::Snapshot;
+pub(crate) use snapshotpath::SnapshotPath;
+pub(crate) use version::{Dedup, Version};
@@ -0,0 +1,117 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+/// A line of `/proc/self/mountinfo`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Mount {
+    /// The directory of the filesystem that is mounted, which is `/` unless a subdirectory was
+    /// bind mounted or, on btrfs, a subvolume was mounted.
+    pub(crate) root: PathBuf,
+    pub(crate) mount_point: PathBuf,
+    pub(crate) fstype: String,
+    /// For ZFS, the name of the dataset or snapshot.
+    pub(crate) source: String,
+}
+
+impl Mount {
+    /// Read all mounts of the current mount namespace.
+    pub(crate) fn read_all() -> Result<Vec<Self>> {
+        let content = fs::read_to_string("/proc/self/mountinfo")
+            .context("could not read /proc/self/mountinfo")?;
+        Self::parse_all(&content)
+    }
+
+    /// Parse the content of a mountinfo file.
+    pub(super) fn parse_all(content: &str) -> Result<Vec<Self>> {
+        content
+            .lines()
+            .map(|line| {
+                Self::parse(line).with_context(|| format!("invalid mountinfo line '{line}'"))
+            })
+            .collect()
+    }
+
+    /// Find the mount the path resides on, which is the one on its nearest ancestor. A later
+    /// mount on the same mountpoint hides the earlier ones.
+    pub(crate) fn containing<'a>(mounts: &'a [Self], path: &Path) -> Option<&'a Self> {
+        path.ancestors()
+            .find_map(|parent| mounts.iter().rev().find(|m| m.mount_point == parent))
+    }
+
+    /// Parse a line like
+    /// `36 35 0:42 / /tank/home rw,relatime shared:1 - zfs tank/home rw,xattr`.
+    fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.split(' ');
+        let root = fields.nth(3)?;
+        let mount_point = fields.next()?;
+        // skip the mount options and the optional fields up to the separator
+        let mut fields = fields.skip_while(|f| *f != "-").skip(1);
+        let fstype = fields.next()?;
+        let source = fields.next()?;
+
+        Some(Self {
+            root: unescape(root).into(),
+            mount_point: unescape(mount_point).into(),
+            fstype: unescape(fstype),
+            source: unescape(source),
+        })
+    }
+}
+
+/// Undo the octal escaping of spaces, tabs, newlines and backslashes, like `\040`.
+fn unescape(field: &str) -> String {
+    let mut result = Vec::with_capacity(field.len());
+    let mut bytes = field.as_bytes();
+    while let Some((&first, rest)) = bytes.split_first() {
+        let octal = rest
+            .get(..3)
+            .filter(|_| first == b'\\')
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .and_then(|digits| u8::from_str_radix(digits, 8).ok());
+        match octal {
+            Some(byte) => {
+                result.push(byte);
+                bytes = &rest[3..];
+            }
+            None => {
+                result.push(first);
+                bytes = rest;
+            }
+        }
+    }
+    String::from_utf8_lossy(&result).into_owned()
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use super::Mount;
+
+    #[test]
+    fn parse_mountinfo_lines() {
+        assert_eq!(
+            Mount::parse(
+                "36 35 0:42 / /tank/my\\040files rw,relatime shared:1 master:2 - zfs tank/files rw"
+            ),
+            Some(Mount {
+                root: PathBuf::from("/"),
+                mount_point: PathBuf::from("/tank/my files"),
+                fstype: "zfs".to_owned(),
+                source: "tank/files".to_owned(),
+            })
+        );
+        assert_eq!(
+            Mount::parse("23 28 0:22 /www /srv/www rw,relatime - zfs tank/data rw"),
+            Some(Mount {
+                root: PathBuf::from("/www"),
+                mount_point: PathBuf::from("/srv/www"),
+                fstype: "zfs".to_owned(),
+                source: "tank/data".to_owned(),
+            })
+        );
+        assert_eq!(Mount::parse("23 28 0:22 / /proc rw,relatime"), None);
+    }
+}
@@ -0,0 +1,185 @@
+use anyhow::{bail, Context, Result};
+use chrono::{NaiveDate, NaiveDateTime};
+use regex::Regex;
+
+/// Templates of the snapshot names created by common auto-snapshot tools.
+///
+/// `%Y`, `%m`, `%d`, `%H`, `%M` and `%S` are the usual date and time fields, `%c` is the frequency
+/// class (`hourly`, `daily`, ...) and `%*` matches anything.
+const BUILTIN_TEMPLATES: &[&str] = &[
+    // sanoid
+    "autosnap_%Y-%m-%d_%H:%M:%S_%c",
+    // zfs-auto-snapshot
+    "zfs-auto-snap_%c-%Y-%m-%d-%H%M",
+    // znap
+    "znap_%Y-%m-%d-%H%M_%c",
+    // zrepl
+    "zrepl_%Y%m%d_%H%M%S_%*",
+    // znapzend
+    "%Y-%m-%d-%H%M%S",
+    // dated backup directories
+    "%Y-%m-%d",
+];
+
+/// The information encoded in the name of a snapshot.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct ParsedName {
+    pub(crate) class: Option<String>,
+    pub(crate) time: NaiveDateTime,
+}
+
+/// A single naming scheme, created from a template like `autosnap_%Y-%m-%d_%H:%M:%S_%c`.
+#[derive(Debug)]
+pub(crate) struct NamingScheme {
+    regex: Regex,
+}
+
+impl NamingScheme {
+    pub(crate) fn new(template: &str) -> Result<Self> {
+        let regex = Regex::new(&template_to_regex(template)?)
+            .with_context(|| format!("invalid snapshot name template '{template}'"))?;
+        Ok(Self { regex })
+    }
+
+    /// Parse the class and the time out of a snapshot name. Return `None` if the name does not
+    /// follow the scheme.
+    pub(crate) fn parse(&self, name: &str) -> Option<ParsedName> {
+        let captures = self.regex.captures(name)?;
+        let field = |name: &str| -> Option<u32> {
+            captures
+                .name(name)
+                .map_or(Some(0), |m| m.as_str().parse().ok())
+        };
+
+        let year = captures.name("Y")?.as_str().parse().ok()?;
+        let time = NaiveDate::from_ymd_opt(year, field("m")?, field("d")?)?.and_hms_opt(
+            field("H")?,
+            field("M")?,
+            field("S")?,
+        )?;
+        let class = captures.name("c").map(|m| m.as_str().to_owned());
+
+        Some(ParsedName { class, time })
+    }
+}
+
+/// All naming schemes that are tried on a snapshot name, in order.
+#[derive(Debug)]
+pub(crate) struct NamingSchemes {
+    schemes: Vec<NamingScheme>,
+}
+
+impl NamingSchemes {
+    /// Create the built-in schemes, preceded by the user-defined templates.
+    pub(crate) fn with_templates(templates: &[String]) -> Result<Self> {
+        let schemes = templates
+            .iter()
+            .map(String::as_str)
+            .chain(BUILTIN_TEMPLATES.iter().copied())
+            .map(NamingScheme::new)
+            .collect::<Result<_>>()?;
+        Ok(Self { schemes })
+    }
+
+    /// Parse a snapshot name with the first scheme that matches.
+    pub(crate) fn parse(&self, name: &str) -> Option<ParsedName> {
+        self.schemes.iter().find_map(|s| s.parse(name))
+    }
+}
+
+impl Default for NamingSchemes {
+    fn default() -> Self {
+        Self::with_templates(&[]).expect("built-in templates are valid")
+    }
+}
+
+/// Translate a template into an anchored regular expression with one named group per field.
+fn template_to_regex(template: &str) -> Result<String> {
+    let mut result = String::from("^");
+    let mut chars = template.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            result.push_str(&regex::escape(&c.to_string()));
+            continue;
+        }
+        let placeholder = chars
+            .next()
+            .with_context(|| format!("template '{template}' ends with '%'"))?;
+        let pattern = match placeholder {
+            'Y' => r"(?P<Y>\d{4})",
+            'm' => r"(?P<m>\d{2})",
+            'd' => r"(?P<d>\d{2})",
+            'H' => r"(?P<H>\d{2})",
+            'M' => r"(?P<M>\d{2})",
+            'S' => r"(?P<S>\d{2})",
+            'c' => r"(?P<c>[[:alpha:]]+)",
+            '*' => r".*",
+            '%' => "%",
+            other => bail!("unknown placeholder '%{other}' in template '{template}'"),
+        };
+        result.push_str(pattern);
+    }
+
+    result.push('$');
+    Ok(result)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{NamingScheme, NamingSchemes, ParsedName};
+    use chrono::NaiveDate;
+
+    fn parsed(class: Option<&str>, ymd: (i32, u32, u32), hms: (u32, u32, u32)) -> ParsedName {
+        ParsedName {
+            class: class.map(str::to_owned),
+            time: NaiveDate::from_ymd_opt(ymd.0, ymd.1, ymd.2)
+                .unwrap()
+                .and_hms_opt(hms.0, hms.1, hms.2)
+                .unwrap(),
+        }
+    }
+
+    #[test]
+    fn parse_builtin_schemes() {
+        let schemes = NamingSchemes::default();
+
+        assert_eq!(
+            schemes.parse("autosnap_2022-11-14_07:30:00_hourly"),
+            Some(parsed(Some("hourly"), (2022, 11, 14), (7, 30, 0)))
+        );
+        assert_eq!(
+            schemes.parse("zfs-auto-snap_daily-2022-11-14-0730"),
+            Some(parsed(Some("daily"), (2022, 11, 14), (7, 30, 0)))
+        );
+        assert_eq!(
+            schemes.parse("znap_2022-11-14-0730_weekly"),
+            Some(parsed(Some("weekly"), (2022, 11, 14), (7, 30, 0)))
+        );
+        assert_eq!(
+            schemes.parse("zrepl_20221114_073012_000"),
+            Some(parsed(None, (2022, 11, 14), (7, 30, 12)))
+        );
+        assert_eq!(
+            schemes.parse("2022-11-14-073012"),
+            Some(parsed(None, (2022, 11, 14), (7, 30, 12)))
+        );
+        assert_eq!(
+            schemes.parse("2022-11-14"),
+            Some(parsed(None, (2022, 11, 14), (0, 0, 0)))
+        );
+        assert_eq!(schemes.parse("manual-before-upgrade"), None);
+        assert_eq!(schemes.parse("autosnap_2022-13-14_07:30:00_hourly"), None);
+    }
+
+    #[test]
+    fn parse_user_template() {
+        let scheme = NamingScheme::new("backup.%c.%Y%m%d").unwrap();
+        assert_eq!(
+            scheme.parse("backup.monthly.20221101"),
+            Some(parsed(Some("monthly"), (2022, 11, 1), (0, 0, 0)))
+        );
+        assert_eq!(scheme.parse("xbackup.monthly.20221101"), None);
+        assert!(NamingScheme::new("snap_%Q").is_err());
+    }
+}
@@ -0,0 +1,161 @@
+use std::fmt::Debug;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+
+use super::cmd::ZfsCommand;
+use super::dirprovider::DirectoryProvider;
+use super::mountinfo::Mount;
+use super::naming::NamingSchemes;
+use super::snapper::SnapperProvider;
+use super::snapshot::Snapshot;
+use super::streamprovider::SendStreamProvider;
+use super::zfsprovider::ZfsProvider;
+
+/// The filesystem types of NFS and SMB mounts, which may be exports of ZFS datasets.
+const NETWORK_FILESYSTEMS: &[&str] = &["nfs", "nfs4", "cifs", "smb3"];
+
+/// A filesystem whose snapshots can be searched, like a ZFS dataset.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Volume {
+    /// The name of the volume, like `tank/home` for a ZFS dataset.
+    pub(crate) name: String,
+    /// Where the live volume is mounted.
+    pub(crate) path: PathBuf,
+}
+
+/// A source of snapshots.
+pub(crate) trait SnapshotProvider: Debug {
+    /// Find the volume containing the absolute path. Return it and the path of the file in the
+    /// live volume, which differs from `path` if it points inside a snapshot.
+    fn find_volume(&self, path: &Path) -> Result<(Volume, PathBuf)>;
+
+    /// List all snapshots of the volume, in any order.
+    fn list_snapshots(&self, volume: &Volume, schemes: &NamingSchemes) -> Result<Vec<Snapshot>>;
+
+    /// Resolve the path of a file, relative to the volume, inside one of its snapshots. The dataset
+    /// looks up every file in its snapshots through this, including those of origins, replicas and
+    /// remotes. By default the snapshot's path is where it holds the copy of the volume's root.
+    fn resolve_path(&self, snapshot: &Snapshot, relative: &Path) -> PathBuf {
+        snapshot.join(relative)
+    }
+
+    /// Whether the volume is a ZFS dataset, which has origins, replicas and keys.
+    fn is_zfs(&self) -> bool {
+        false
+    }
+}
+
+/// Choose the provider for the absolute path. Configured directory snapshots and send streams take
+/// precedence, the one with the most specific path first, then the filesystem the path resides on
+/// decides. A matching mapping replaces the snapshots of the filesystem, they are not merged, since
+/// the mapped path need not be the root of a dataset or subvolume.
+pub(crate) fn provider_for(
+    path: &Path,
+    zfs: &ZfsCommand,
+    directory_snapshots: &[String],
+    send_streams: &[String],
+) -> Result<Box<dyn SnapshotProvider>> {
+    let directories = directory_snapshots
+        .iter()
+        .map(|mapping| DirectoryProvider::parse(mapping))
+        .collect::<Result<Vec<_>>>()
+        .context("parsing DirectorySnapshots")?;
+    if let Some(directory) = directories
+        .into_iter()
+        .filter(|d| d.contains(path))
+        .max_by_key(|d| d.specificity(path))
+    {
+        return Ok(Box::new(directory));
+    }
+    let streams = send_streams
+        .iter()
+        .map(|mapping| SendStreamProvider::parse(mapping, path))
+        .collect::<Result<Vec<_>>>()
+        .context("parsing SendStreams")?;
+    if let Some(streams) = streams
+        .into_iter()
+        .filter(|s| s.contains(path))
+        .max_by_key(SendStreamProvider::specificity)
+    {
+        return Ok(Box::new(streams));
+    }
+
+    let mounts = Mount::read_all()?;
+    Ok(provider_for_mount(Mount::containing(&mounts, path), zfs))
+}
+
+/// Choose the provider for the filesystem mounted by `mount`. The snapshots of a ZFS dataset
+/// exported over NFS or SMB are found in `.zfs/snapshot` of the export root, if the server exposes
+/// it. Child datasets exported over the same mount with `crossmnt` look like plain directories to
+/// the client, so their files are looked up in the snapshots of the export root, too.
+fn provider_for_mount(mount: Option<&Mount>, zfs: &ZfsCommand) -> Box<dyn SnapshotProvider> {
+    match mount {
+        Some(mount) if mount.fstype == "btrfs" => Box::new(SnapperProvider),
+        Some(mount)
+            if NETWORK_FILESYSTEMS.contains(&mount.fstype.as_str())
+                && mount.mount_point.join(".zfs/snapshot").is_dir() =>
+        {
+            Box::new(DirectoryProvider::new(
+                mount.mount_point.clone(),
+                mount.mount_point.join(".zfs/snapshot"),
+            ))
+        }
+        _ => Box::new(ZfsProvider::new(zfs.clone())),
+    }
+}
+
+/// Read a directory containing one directory per snapshot. Snapshots are returned in ascending
+/// order, by the time in their name or else alphabetically.
+pub(super) fn read_snapshot_dir(path: &Path, schemes: &NamingSchemes) -> Result<Vec<Snapshot>> {
+    let mut errors = vec![];
+    let mut result: Vec<_> = path
+        .read_dir()
+        .with_context(|| format!("could not read snapshot dir `{path:?}`"))?
+        .filter_map(|r| r.map_err(|e| errors.push(e)).ok())
+        .map(|i| Snapshot::new(i.path(), schemes))
+        .collect();
+
+    if !errors.is_empty() {
+        bail!("aggregation of snapshots failed, {:?}", errors);
+    }
+
+    result.sort_unstable();
+    Ok(result)
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::path::Path;
+
+    use super::provider_for_mount;
+    use crate::zfs::cmd::ZfsCommand;
+    use crate::zfs::mountinfo::Mount;
+
+    #[test]
+    fn find_snapshots_of_nfs_exports() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join(".zfs/snapshot/daily")).unwrap();
+        let mount = |fstype: &str, mount_point: &Path| Mount {
+            root: "/".into(),
+            mount_point: mount_point.to_path_buf(),
+            fstype: fstype.to_owned(),
+            source: "server:/tank/home".to_owned(),
+        };
+        let zfs = ZfsCommand::default();
+
+        let provider = provider_for_mount(Some(&mount("nfs4", root)), &zfs);
+        let (volume, live_path) = provider
+            .find_volume(&root.join(".zfs/snapshot/daily/penguin/a"))
+            .unwrap();
+        assert_eq!(volume.path, root);
+        assert_eq!(live_path, root.join("penguin/a"));
+
+        // an export without snapshots and a local filesystem are left to zfs
+        let other = root.join(".zfs/snapshot/daily");
+        assert!(provider_for_mount(Some(&mount("nfs4", &other)), &zfs).is_zfs());
+        assert!(provider_for_mount(Some(&mount("ext4", root)), &zfs).is_zfs());
+    }
+}
@@ -0,0 +1,331 @@
+use std::cell::{OnceCell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use anyhow::{bail, Context, Result};
+use smart_default::SmartDefault;
+
+use super::naming::NamingSchemes;
+use super::snapshot::Snapshot;
+use super::staging::Staging;
+use crate::misc::ToStr;
+
+/// How to run a command on another host, with `%h` standing for the host. The command to run is
+/// appended as the last argument.
+#[derive(Debug, Clone, SmartDefault)]
+pub(crate) struct RemoteCommand {
+    #[default(vec!["ssh".to_owned(), "%h".to_owned()])]
+    pub(crate) args: Vec<String>,
+}
+
+/// A directory on another host whose `.zfs/snapshot` holds snapshots of a local path, configured
+/// as `<path>=<host>:<directory>`.
+#[derive(Debug, PartialEq, Eq)]
+struct Remote {
+    root: PathBuf,
+    host: String,
+    directory: PathBuf,
+}
+
+impl Remote {
+    fn parse(mapping: &str) -> Result<Self> {
+        let (root, remote) = mapping
+            .split_once('=')
+            .with_context(|| format!("remote '{mapping}' is not <path>=<host>:<directory>"))?;
+        let (host, directory) = remote
+            .split_once(':')
+            .with_context(|| format!("remote '{mapping}' is not <path>=<host>:<directory>"))?;
+        if !Path::new(root).is_absolute() || !Path::new(directory).is_absolute() {
+            bail!("remote '{mapping}' must map an absolute path to an absolute path");
+        }
+        Ok(Self {
+            root: root.into(),
+            host: host.to_owned(),
+            directory: directory.into(),
+        })
+    }
+}
+
+/// How to fetch the file from a remote snapshot containing it.
+#[derive(Debug)]
+struct Download {
+    host: String,
+    /// The `.zfs/snapshot` directory on the host.
+    snapshot_dir: PathBuf,
+    /// The file relative to `snapshot_dir`, starting with the name of the snapshot.
+    file: PathBuf,
+    /// Where `tar` extracts the file to.
+    downloads: PathBuf,
+    /// Where the file belongs in the local directory of the snapshot.
+    target: PathBuf,
+}
+
+/// Snapshots on other hosts, like a backup server with longer retention. Each snapshot is a
+/// directory in a private staging directory, into which the file is fetched on demand.
+#[derive(Debug)]
+pub(crate) struct RemoteSnapshots {
+    command: RemoteCommand,
+    staging: OnceCell<Staging>,
+    /// The downloads of the snapshots containing the file, by the path of the local directory.
+    downloads: RefCell<HashMap<PathBuf, Download>>,
+}
+
+impl RemoteSnapshots {
+    pub(crate) fn new(command: RemoteCommand) -> Self {
+        Self {
+            command,
+            staging: OnceCell::new(),
+            downloads: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Whether the remote snapshot contains the file, as told when listing it.
+    pub(crate) fn has_file(&self, snapshot: &Snapshot) -> bool {
+        self.downloads.borrow().contains_key(snapshot.path())
+    }
+
+    /// Copy the file from the remote snapshot with `tar`, unless it was copied before.
+    pub(crate) fn fetch(&self, snapshot: &Snapshot) -> Result<()> {
+        let downloads = self.downloads.borrow();
+        let Some(download) = downloads.get(snapshot.path()) else {
+            return Ok(());
+        };
+        if download.target.symlink_metadata().is_ok() {
+            return Ok(());
+        }
+
+        let snapshot_dir = quote(download.snapshot_dir.as_path().to_str_anyhow()?);
+        let file = quote(download.file.as_path().to_str_anyhow()?);
+        self.download(
+            &download.host,
+            &format!("cd {snapshot_dir} && tar -cf - -- {file}"),
+            &download.downloads,
+        )
+        .with_context(|| format!("could not fetch {snapshot} from {}", download.host))?;
+        if let Some(parent) = download.target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let downloaded = download.downloads.join(&download.file);
+        fs::rename(&downloaded, &download.target)
+            .with_context(|| format!("could not move {downloaded:?}"))
+    }
+
+    /// List the snapshots of all remotes configured for `path`, the absolute path of the file,
+    /// with the file at `relative`. Snapshots named like one of `known` are skipped, since they
+    /// are taken to be replicas of local snapshots, by name alone. The file is not fetched yet.
+    pub(crate) fn list(
+        &self,
+        remotes: &[String],
+        path: &Path,
+        relative: &Path,
+        known: &HashSet<&str>,
+        schemes: &NamingSchemes,
+    ) -> Result<Vec<Snapshot>> {
+        let mut result = vec![];
+        for (index, mapping) in remotes.iter().enumerate() {
+            let remote = Remote::parse(mapping)?;
+            let Ok(remote_relative) = path.strip_prefix(&remote.root) else {
+                continue;
+            };
+            let snapshots = self
+                .list_from(&remote, index, remote_relative, relative, known, schemes)
+                .with_context(|| format!("could not search {}", remote.host))?;
+            result.extend(snapshots);
+        }
+        Ok(result)
+    }
+
+    /// List the snapshots of the remote and whether they contain the file.
+    fn list_from(
+        &self,
+        remote: &Remote,
+        index: usize,
+        remote_relative: &Path,
+        relative: &Path,
+        known: &HashSet<&str>,
+        schemes: &NamingSchemes,
+    ) -> Result<Vec<Snapshot>> {
+        let snapshot_dir = remote.directory.join(".zfs/snapshot");
+        let quoted_dir = quote(snapshot_dir.as_path().to_str_anyhow()?);
+        let file = quote(remote_relative.to_str_anyhow()?);
+        let listing = self.run(
+            &remote.host,
+            &format!(
+                "cd {quoted_dir} || exit 1; for s in *; do [ -d \"$s\" ] || continue; \
+                 if [ -e \"$s\"/{file} ] || [ -h \"$s\"/{file} ]; then echo \"+$s\"; \
+                 else echo \"-$s\"; fi; done"
+            ),
+        )?;
+        let mut present = vec![];
+        let mut absent = vec![];
+        for line in listing.lines() {
+            match line.split_at_checked(1) {
+                Some(("+", name)) if !known.contains(name) => present.push(name),
+                Some(("-", name)) if !known.contains(name) => absent.push(name),
+                _ => {}
+            }
+        }
+
+        let staging = Staging::get_or_create(&self.staging)?
+            .path()
+            .join(index.to_string());
+        let downloads = staging.join(".download");
+        fs::create_dir_all(&downloads)?;
+
+        let mut result = vec![];
+        for name in present.iter().chain(&absent) {
+            let path = staging.join(name);
+            fs::create_dir(&path).with_context(|| format!("could not create {path:?}"))?;
+            if present.contains(name) {
+                let download = Download {
+                    host: remote.host.clone(),
+                    snapshot_dir: snapshot_dir.clone(),
+                    file: Path::new(name).join(remote_relative),
+                    downloads: downloads.clone(),
+                    target: path.join(relative),
+                };
+                self.downloads.borrow_mut().insert(path.clone(), download);
+            }
+
+            let mut snapshot = Snapshot::new(path, schemes);
+            snapshot.set_dataset(&format!("{}:{}", remote.host, remote.directory.display()));
+            result.push(snapshot);
+        }
+        Ok(result)
+    }
+
+    fn command(&self, host: &str, script: &str) -> Result<Command> {
+        let args: Vec<_> = self
+            .command
+            .args
+            .iter()
+            .map(|a| a.replace("%h", host))
+            .collect();
+        let (program, args) = args.split_first().context("RemoteCommand is empty")?;
+        let mut command = Command::new(program);
+        command.args(args).arg(script);
+        Ok(command)
+    }
+
+    /// Run the script on the host and return its output.
+    fn run(&self, host: &str, script: &str) -> Result<String> {
+        let output = self
+            .command(host, script)?
+            .stderr(Stdio::inherit())
+            .output()
+            .context("could not run RemoteCommand")?;
+        if !output.status.success() {
+            bail!("listing the snapshots failed ({})", output.status);
+        }
+        String::from_utf8(output.stdout).context("the list of snapshots is invalid UTF8")
+    }
+
+    /// Run the script on the host and extract the tar archive it writes into `target`.
+    fn download(&self, host: &str, script: &str, target: &Path) -> Result<()> {
+        let mut remote = self
+            .command(host, script)?
+            .stdout(Stdio::piped())
+            .spawn()
+            .context("could not run RemoteCommand")?;
+        let stdout = remote.stdout.take().context("no output of RemoteCommand")?;
+        let tar = Command::new("tar")
+            .arg("-xf")
+            .arg("-")
+            .arg("-C")
+            .arg(target)
+            .stdin(stdout)
+            .status()
+            .context("error running `tar`")?;
+        let status = remote.wait()?;
+        if !status.success() || !tar.success() {
+            bail!("downloading the file failed ({status}, tar: {tar})");
+        }
+        Ok(())
+    }
+}
+
+/// Quote a string for a POSIX shell.
+fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+    use std::fs;
+    use std::path::Path;
+
+    use super::{quote, Remote, RemoteCommand, RemoteSnapshots};
+    use crate::zfs::NamingSchemes;
+
+    #[test]
+    fn parse_remotes() {
+        assert_eq!(
+            Remote::parse("/home=backup:/backup/home").unwrap(),
+            Remote {
+                root: "/home".into(),
+                host: "backup".to_owned(),
+                directory: "/backup/home".into(),
+            }
+        );
+        assert!(Remote::parse("/home=backup").is_err());
+        assert!(Remote::parse("/home=backup:backup/home").is_err());
+        assert_eq!(quote("it's"), r"'it'\''s'");
+    }
+
+    #[test]
+    fn fetch_through_local_shell() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let snapshots = root.join("backup/.zfs/snapshot");
+        for (name, content) in [
+            ("older", Some("old")),
+            ("local", Some("same")),
+            ("new", None),
+        ] {
+            let dir = snapshots.join(name).join("penguin");
+            fs::create_dir_all(&dir).unwrap();
+            if let Some(content) = content {
+                fs::write(dir.join("it's.txt"), content).unwrap();
+            }
+        }
+
+        let remotes = RemoteSnapshots::new(RemoteCommand {
+            args: vec!["sh".to_owned(), "-c".to_owned()],
+        });
+        let mapping = format!("/home=backup.example.com:{}", root.join("backup").display());
+        let mut found = remotes
+            .list(
+                &[mapping, "/srv=other:/srv".to_owned()],
+                Path::new("/home/penguin/it's.txt"),
+                Path::new("home/penguin/it's.txt"),
+                &HashSet::from(["local"]),
+                &NamingSchemes::default(),
+            )
+            .unwrap();
+        found.sort_unstable_by_key(|s| s.name().to_owned());
+
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].name(), "new");
+        let relative = Path::new("home/penguin/it's.txt");
+        assert!(!found[0].join(relative).exists());
+        assert!(!remotes.has_file(&found[0]));
+        assert_eq!(found[1].name(), "older");
+        assert!(found[1]
+            .dataset()
+            .unwrap()
+            .starts_with("backup.example.com:"));
+        assert!(remotes.has_file(&found[1]));
+        // nothing is copied before it is needed
+        assert!(!found[1].join(relative).exists());
+        remotes.fetch(&found[1]).unwrap();
+        remotes.fetch(&found[1]).unwrap();
+        let file = found[1].join(relative);
+        assert_eq!(fs::read_to_string(file).unwrap(), "old");
+
+        drop(remotes);
+        assert!(!found[1].path().exists());
+    }
+}
@@ -0,0 +1,252 @@
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use smart_default::SmartDefault;
+
+use super::fileinfo::FileInfo;
+use super::snapshot::Snapshot;
+
+/// How snapshots are probed for a file.
+#[derive(Debug, Clone, Copy, SmartDefault)]
+pub(crate) struct ScanOptions {
+    /// The number of snapshots probed at the same time.
+    #[default(8)]
+    pub(crate) threads: usize,
+    /// Stop probing after this time and report what was found so far.
+    pub(crate) time_budget: Option<Duration>,
+    /// Probes taking longer than this are reported as slow, which is usually due to mounting the
+    /// snapshot.
+    #[default(Duration::from_secs(1))]
+    pub(crate) slow_threshold: Duration,
+}
+
+/// The result of probing snapshots for a file.
+#[derive(Debug)]
+pub(super) struct Scan {
+    /// The metadata of the file for each snapshot, in snapshot order. `None` if the snapshot does
+    /// not contain the file, could not be probed or was not probed.
+    pub(super) infos: Vec<Option<FileInfo>>,
+    /// The snapshots that could not be probed, other than because the file is absent.
+    pub(super) errors: Vec<(usize, anyhow::Error)>,
+    /// The snapshots that were not probed within the time budget.
+    pub(super) unfinished: Vec<usize>,
+    /// The snapshots that were slow to probe.
+    pub(super) slow: Vec<(usize, Duration)>,
+}
+
+impl Scan {
+    /// Probe the snapshots for the file in parallel, newest first, given the path of the file in
+    /// each snapshot. If `newest_only` is set, stop as soon as the newest snapshot containing the
+    /// file is known.
+    pub(super) fn run(paths: &[PathBuf], options: &ScanOptions, newest_only: bool) -> Self {
+        let start = Instant::now();
+        let newest_first: Arc<Vec<PathBuf>> = Arc::new(paths.iter().rev().cloned().collect());
+        let next = Arc::new(AtomicUsize::new(0));
+        let stop = Arc::new(AtomicBool::new(false));
+        let (sender, receiver) = mpsc::channel();
+
+        // the threads are not joined, so a probe hanging in an automount does not block us
+        for _ in 0..options.threads.clamp(1, paths.len().max(1)) {
+            let (newest_first, next, stop, sender) = (
+                newest_first.clone(),
+                next.clone(),
+                stop.clone(),
+                sender.clone(),
+            );
+            thread::spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(path) = newest_first.get(i) else {
+                        break;
+                    };
+                    let probe_start = Instant::now();
+                    let info = probe(path);
+                    if sender.send((i, info, probe_start.elapsed())).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+
+        let len = paths.len();
+        let mut results: Vec<Option<Result<Option<FileInfo>>>> = (0..len).map(|_| None).collect();
+        let mut slow = vec![];
+        loop {
+            let message = match options.time_budget {
+                Some(budget) => match budget.checked_sub(start.elapsed()) {
+                    Some(remaining) => receiver.recv_timeout(remaining).ok(),
+                    None => None,
+                },
+                None => receiver.recv().ok(),
+            };
+            let Some((i, info, duration)) = message else {
+                break;
+            };
+
+            if duration > options.slow_threshold {
+                slow.push((len - 1 - i, duration));
+            }
+            results[i] = Some(info);
+
+            if newest_only && newest_is_known(&results) {
+                break;
+            }
+        }
+        stop.store(true, Ordering::Relaxed);
+
+        // snapshots older than the newest one containing the file were not needed anyway
+        let unfinished = if newest_only && newest_is_known(&results) {
+            vec![]
+        } else {
+            (0..len)
+                .filter(|i| results[len - 1 - i].is_none())
+                .collect()
+        };
+
+        let mut infos = vec![];
+        let mut errors = vec![];
+        for (i, result) in results.into_iter().enumerate().rev() {
+            infos.push(match result {
+                Some(Ok(info)) => info,
+                Some(Err(e)) => {
+                    errors.push((len - 1 - i, e));
+                    None
+                }
+                None => None,
+            });
+        }
+        slow.sort_unstable();
+
+        Self {
+            infos,
+            errors,
+            unfinished,
+            slow,
+        }
+    }
+
+    /// Warn about snapshots that were slow, could not be probed or could not be probed within the
+    /// time budget.
+    pub(super) fn report(&self, snapshots: &[Snapshot]) {
+        for (i, e) in &self.errors {
+            eprintln!("could not search {}: {e:#}", snapshots[*i].name());
+        }
+        if !self.slow.is_empty() {
+            let names: Vec<_> = self
+                .slow
+                .iter()
+                .map(|(i, d)| format!("{} ({:.1}s)", snapshots[*i].name(), d.as_secs_f64()))
+                .collect();
+            eprintln!("slow to mount: {}", names.join(", "));
+        }
+        if !self.unfinished.is_empty() {
+            let names: Vec<_> = self
+                .unfinished
+                .iter()
+                .map(|i| snapshots[*i].name())
+                .collect();
+            eprintln!(
+                "time budget exceeded, results are incomplete. Not searched: {}",
+                names.join(", ")
+            );
+        }
+    }
+}
+
+/// Read the metadata of the file in a snapshot. A missing file or parent directory means the
+/// snapshot does not contain it, any other error is returned.
+fn probe(path: &Path) -> Result<Option<FileInfo>> {
+    match FileInfo::read(path) {
+        Ok(info) => Ok(Some(info)),
+        Err(e) => match e.downcast_ref::<io::Error>().map(io::Error::kind) {
+            Some(ErrorKind::NotFound | ErrorKind::NotADirectory) => Ok(None),
+            _ => Err(e),
+        },
+    }
+}
+
+/// Check if the newest snapshot containing the file is known, given the results ordered newest
+/// first: a snapshot contains the file and all newer ones have been probed. Snapshots that could
+/// not be probed are reported and passed over.
+fn newest_is_known(results: &[Option<Result<Option<FileInfo>>>]) -> bool {
+    for result in results {
+        match result {
+            None => return false,
+            Some(Ok(Some(_))) => return true,
+            Some(Ok(None) | Err(_)) => (),
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::{Scan, ScanOptions};
+
+    #[test]
+    fn results_are_in_snapshot_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let contains = [true, false, true, true, false, false, true, false];
+        let paths: Vec<_> = contains
+            .iter()
+            .enumerate()
+            .map(|(i, contains)| {
+                let dir = root.join(format!("{i}"));
+                fs::create_dir_all(&dir).unwrap();
+                if *contains {
+                    fs::write(dir.join("file"), "x".repeat(i)).unwrap();
+                }
+                dir.join("file")
+            })
+            .collect();
+        let options = ScanOptions {
+            threads: 3,
+            ..Default::default()
+        };
+
+        let scan = Scan::run(&paths, &options, false);
+        let sizes: Vec<_> = scan
+            .infos
+            .iter()
+            .map(|i| i.as_ref().map(|i| i.size))
+            .collect();
+        assert_eq!(
+            sizes,
+            [Some(0), None, Some(2), Some(3), None, None, Some(6), None]
+        );
+        assert!(scan.unfinished.is_empty());
+        assert!(scan.errors.is_empty());
+
+        let scan = Scan::run(&paths, &options, true);
+        assert_eq!(scan.infos.iter().rposition(Option::is_some), Some(6));
+        assert!(scan.unfinished.is_empty());
+    }
+
+    #[test]
+    fn errors_are_not_absence() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths: Vec<_> = ["missing", "looping"]
+            .iter()
+            .map(|name| {
+                let dir = dir.path().join(name);
+                fs::create_dir_all(&dir).unwrap();
+                dir.join("loop/file")
+            })
+            .collect();
+        std::os::unix::fs::symlink("loop", dir.path().join("looping/loop")).unwrap();
+
+        let scan = Scan::run(&paths, &ScanOptions::default(), false);
+        assert!(scan.infos.iter().all(Option::is_none));
+        let errors: Vec<_> = scan.errors.iter().map(|(i, _)| *i).collect();
+        assert_eq!(errors, [1]);
+    }
+}
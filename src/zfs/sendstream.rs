@@ -0,0 +1,402 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use anyhow::{bail, Context, Result};
+
+/// The size of every record header of a stream (`dmu_replay_record_t`).
+const RECORD_SIZE: usize = 312;
+/// The header fields of a record start after its type and payload length.
+const FIELDS: usize = 8;
+const BACKUP_MAGIC: u64 = 0x2F5BACBAC;
+/// The largest bonus buffer, that of a 16 KiB dnode.
+const MAX_BONUS_LENGTH: u32 = 16 * 1024;
+/// The largest block size ZFS supports.
+const MAX_BLOCK_SIZE: u32 = 16 * 1024 * 1024;
+
+const DRR_BEGIN: u32 = 0;
+const DRR_OBJECT: u32 = 1;
+const DRR_FREEOBJECTS: u32 = 2;
+const DRR_WRITE: u32 = 3;
+const DRR_FREE: u32 = 4;
+const DRR_END: u32 = 5;
+const DRR_WRITE_BYREF: u32 = 6;
+const DRR_SPILL: u32 = 7;
+const DRR_WRITE_EMBEDDED: u32 = 8;
+const DRR_OBJECT_RANGE: u32 = 9;
+const DRR_REDACT: u32 = 10;
+
+/// The stream type in the lowest bits of the version info of the BEGIN record.
+const COMPOUND_STREAM: u64 = 2;
+/// The feature flag of raw (`zfs send -w`) streams, shifted by the stream type bits.
+const FEATURE_RAW: u64 = 1 << (24 + 2);
+/// The object set type of a filesystem, as opposed to a volume.
+const OBJSET_TYPE_ZFS: u32 = 2;
+
+/// The byte order of a stream, which is that of the host that sent it. It applies to the data
+/// blocks in the stream, too.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum ByteOrder {
+    Little,
+    Big,
+}
+
+impl ByteOrder {
+    pub(super) fn u16(self, bytes: &[u8], offset: usize) -> Result<u16> {
+        let field = field(bytes, offset)?;
+        Ok(match self {
+            Self::Little => u16::from_le_bytes(field),
+            Self::Big => u16::from_be_bytes(field),
+        })
+    }
+
+    pub(super) fn u32(self, bytes: &[u8], offset: usize) -> Result<u32> {
+        let field = field(bytes, offset)?;
+        Ok(match self {
+            Self::Little => u32::from_le_bytes(field),
+            Self::Big => u32::from_be_bytes(field),
+        })
+    }
+
+    pub(super) fn u64(self, bytes: &[u8], offset: usize) -> Result<u64> {
+        let field = field(bytes, offset)?;
+        Ok(match self {
+            Self::Little => u64::from_le_bytes(field),
+            Self::Big => u64::from_be_bytes(field),
+        })
+    }
+}
+
+fn field<const N: usize>(bytes: &[u8], offset: usize) -> Result<[u8; N]> {
+    bytes
+        .get(offset..offset + N)
+        .and_then(|b| b.try_into().ok())
+        .context("unexpected end of data")
+}
+
+/// The BEGIN record of a stream, which tells what snapshot it contains.
+#[derive(Debug, Clone)]
+pub(super) struct StreamHeader {
+    pub(super) path: PathBuf,
+    pub(super) byte_order: ByteOrder,
+    /// The GUID of the snapshot in the stream.
+    pub(super) to_guid: u64,
+    /// The GUID of the snapshot an incremental stream is based on, 0 for a full stream.
+    pub(super) from_guid: u64,
+    /// The full name of the snapshot, like `tank/home@daily`.
+    pub(super) to_name: String,
+    /// When the snapshot was taken, in seconds since the epoch.
+    pub(super) creation_time: i64,
+}
+
+impl StreamHeader {
+    /// Read the header of the stream file at `path`. Return `None` if it is not a stream.
+    pub(super) fn read(path: &Path) -> Result<Option<Self>> {
+        let mut record = [0; RECORD_SIZE];
+        let mut file = File::open(path).with_context(|| format!("could not open {path:?}"))?;
+        if file.read_exact(&mut record).is_err() {
+            return Ok(None);
+        }
+        let fields = &record[FIELDS..];
+        let byte_order = if ByteOrder::Little.u64(fields, 0)? == BACKUP_MAGIC {
+            ByteOrder::Little
+        } else if ByteOrder::Big.u64(fields, 0)? == BACKUP_MAGIC {
+            ByteOrder::Big
+        } else {
+            return Ok(None);
+        };
+        if byte_order.u32(&record, 0)? != DRR_BEGIN {
+            return Ok(None);
+        }
+
+        let version_info = byte_order.u64(fields, 8)?;
+        if version_info & 0x3 == COMPOUND_STREAM {
+            bail!("{path:?} is a replication stream (zfs send -R), which is not supported");
+        }
+        if version_info & FEATURE_RAW != 0 {
+            bail!("{path:?} is a raw stream (zfs send -w), which is not supported");
+        }
+        if byte_order.u32(fields, 24)? != OBJSET_TYPE_ZFS {
+            bail!("{path:?} does not contain a filesystem");
+        }
+        let name = &fields[48..48 + 256];
+        let name_length = name.iter().position(|b| *b == 0).unwrap_or(name.len());
+
+        Ok(Some(Self {
+            path: path.to_path_buf(),
+            byte_order,
+            creation_time: byte_order.u64(fields, 16)? as i64,
+            to_guid: byte_order.u64(fields, 32)?,
+            from_guid: byte_order.u64(fields, 40)?,
+            to_name: String::from_utf8_lossy(&name[..name_length]).into_owned(),
+        }))
+    }
+
+    /// The name of the snapshot without the dataset.
+    pub(super) fn snapshot_name(&self) -> &str {
+        self.to_name
+            .split_once('@')
+            .map_or(self.to_name.as_str(), |(_, name)| name)
+    }
+
+    /// The name of the dataset the snapshot was taken of.
+    pub(super) fn dataset_name(&self) -> &str {
+        self.to_name
+            .split_once('@')
+            .map_or(self.to_name.as_str(), |(dataset, _)| dataset)
+    }
+}
+
+/// A data block of an object, located in one of the stream files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Extent {
+    stream: usize,
+    position: u64,
+    length: u64,
+}
+
+/// An object of the object set, like a file or a directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct Object {
+    object_type: u32,
+    pub(super) block_size: u32,
+    pub(super) bonus_type: u32,
+    pub(super) bonus: Vec<u8>,
+    /// The data blocks by their offset. Missing blocks are holes.
+    blocks: BTreeMap<u64, Extent>,
+}
+
+/// The objects of a snapshot, as received from a full stream and the incremental streams on top
+/// of it. Data blocks are not loaded, but read from the stream files when needed. A clone shares
+/// the objects with the original until a stream applied to either changes them.
+#[derive(Debug, Clone)]
+pub(super) struct ObjectSet {
+    pub(super) byte_order: ByteOrder,
+    objects: HashMap<u64, Rc<Object>>,
+    streams: Vec<Rc<File>>,
+}
+
+impl ObjectSet {
+    /// Receive a full stream.
+    pub(super) fn receive(header: &StreamHeader) -> Result<Self> {
+        let mut result = Self {
+            byte_order: header.byte_order,
+            objects: HashMap::new(),
+            streams: vec![],
+        };
+        result.apply(header)?;
+        Ok(result)
+    }
+
+    /// Receive an incremental stream on top of the snapshot it is based on, which is replaced by
+    /// the snapshot in the stream.
+    pub(super) fn apply(&mut self, header: &StreamHeader) -> Result<()> {
+        if header.byte_order != self.byte_order {
+            bail!(
+                "{:?} was sent by a host of different endianness",
+                header.path
+            );
+        }
+        self.read_records(header)
+            .with_context(|| format!("could not read stream {:?}", header.path))
+    }
+
+    fn read_records(&mut self, header: &StreamHeader) -> Result<()> {
+        let file = File::open(&header.path)?;
+        let mut reader = BufReader::new(file.try_clone()?);
+        let stream = self.streams.len();
+        self.streams.push(Rc::new(file));
+
+        let order = self.byte_order;
+        let mut record = [0; RECORD_SIZE];
+        let mut position = 0;
+        loop {
+            reader
+                .read_exact(&mut record)
+                .context("stream ends before its END record")?;
+            position += RECORD_SIZE as u64;
+            let fields = &record[FIELDS..];
+
+            let payload = match order.u32(&record, 0)? {
+                DRR_BEGIN if position == RECORD_SIZE as u64 => order.u32(&record, 4)?.into(),
+                DRR_BEGIN => bail!("nested streams are not supported"),
+                DRR_END => return Ok(()),
+                DRR_OBJECT => {
+                    let bonus_length = order.u32(fields, 20)?;
+                    let raw_bonus_length = order.u32(fields, 28)?;
+                    let payload = match raw_bonus_length {
+                        0 => bonus_length.next_multiple_of(8),
+                        raw => raw,
+                    };
+                    if payload > MAX_BONUS_LENGTH {
+                        bail!("object has a bonus buffer of {payload} bytes");
+                    }
+                    let mut bonus = vec![0; payload as usize];
+                    reader.read_exact(&mut bonus)?;
+                    position += u64::from(payload);
+                    bonus.truncate(bonus_length as usize);
+                    self.receive_object(fields, bonus)?;
+                    0
+                }
+                DRR_FREEOBJECTS => {
+                    let first = order.u64(fields, 0)?;
+                    let end = first.saturating_add(order.u64(fields, 8)?);
+                    self.objects.retain(|id, _| !(first..end).contains(id));
+                    0
+                }
+                DRR_WRITE => {
+                    if fields[42] != 0 {
+                        bail!("compressed streams (zfs send -c) are not supported");
+                    }
+                    let id = order.u64(fields, 0)?;
+                    let offset = order.u64(fields, 16)?;
+                    let length = order.u64(fields, 24)?;
+                    if offset.checked_add(length).is_none() {
+                        bail!("a block of object {id} ends beyond the largest offset");
+                    }
+                    let extent = Extent {
+                        stream,
+                        position,
+                        length,
+                    };
+                    if let Some(object) = self.objects.get_mut(&id) {
+                        if length > u64::from(object.block_size) {
+                            bail!("a block of object {id} is larger than its block size");
+                        }
+                        Rc::make_mut(object).blocks.insert(offset, extent);
+                    }
+                    length
+                }
+                DRR_FREE => {
+                    let offset = order.u64(fields, 8)?;
+                    let end = offset.saturating_add(order.u64(fields, 16)?);
+                    if let Some(object) = self.objects.get_mut(&order.u64(fields, 0)?) {
+                        Rc::make_mut(object)
+                            .blocks
+                            .retain(|block, _| !(offset..end).contains(block));
+                    }
+                    0
+                }
+                DRR_WRITE_BYREF => bail!("deduplicated streams (zfs send -D) are not supported"),
+                DRR_WRITE_EMBEDDED => {
+                    bail!("streams with embedded blocks (zfs send -e) are not supported")
+                }
+                // spill blocks only hold attributes that are not needed
+                DRR_SPILL => match order.u64(fields, 32)? {
+                    0 => order.u64(fields, 8)?,
+                    compressed => compressed,
+                },
+                DRR_OBJECT_RANGE | DRR_REDACT => 0,
+                other => bail!("unknown record type {other}"),
+            };
+            skip(&mut reader, payload)?;
+            position += payload;
+        }
+    }
+
+    /// Create or update an object. An object whose type or block size changed was freed and
+    /// allocated again, so its old data is gone.
+    fn receive_object(&mut self, fields: &[u8], bonus: Vec<u8>) -> Result<()> {
+        let order = self.byte_order;
+        let id = order.u64(fields, 0)?;
+        let object_type = order.u32(fields, 8)?;
+        let bonus_type = order.u32(fields, 12)?;
+        let block_size = order.u32(fields, 16)?;
+        if block_size > MAX_BLOCK_SIZE {
+            bail!("object {id} has a block size of {block_size} bytes");
+        }
+
+        match self.objects.get_mut(&id) {
+            Some(object)
+                if object.object_type == object_type && object.block_size == block_size =>
+            {
+                let object = Rc::make_mut(object);
+                object.bonus_type = bonus_type;
+                object.bonus = bonus;
+            }
+            _ => {
+                let object = Object {
+                    object_type,
+                    block_size,
+                    bonus_type,
+                    bonus,
+                    blocks: BTreeMap::new(),
+                };
+                self.objects.insert(id, Rc::new(object));
+            }
+        }
+        Ok(())
+    }
+
+    pub(super) fn object(&self, id: u64) -> Result<&Object> {
+        self.shared_object(id).map(Rc::as_ref)
+    }
+
+    /// The object, shared with the object sets it has not changed in since.
+    pub(super) fn shared_object(&self, id: u64) -> Result<&Rc<Object>> {
+        self.objects
+            .get(&id)
+            .with_context(|| format!("object {id} is missing from the stream"))
+    }
+
+    /// Read the data blocks of the object with their offsets. Holes are left out, so no more is
+    /// allocated than was received, however sparse the object is.
+    pub(super) fn read_blocks(&self, id: u64) -> Result<Vec<(u64, Vec<u8>)>> {
+        self.object(id)?
+            .blocks
+            .iter()
+            .map(|(offset, extent)| {
+                let mut data = vec![0; extent.length as usize];
+                self.streams[extent.stream]
+                    .read_exact_at(&mut data, extent.position)
+                    .with_context(|| format!("could not read data of object {id}"))?;
+                Ok((*offset, data))
+            })
+            .collect()
+    }
+
+    /// Read the first `length` bytes of the data of the object, like the target of a symlink,
+    /// which must have been received without holes. A larger `length`, as from a corrupt stream,
+    /// is an error rather than an allocation beyond the bytes received.
+    pub(super) fn read(&self, id: u64, length: u64) -> Result<Vec<u8>> {
+        let mut result = vec![];
+        for (offset, extent) in self.object(id)?.blocks.range(..length) {
+            if *offset != result.len() as u64 {
+                break;
+            }
+            let end = offset.saturating_add(extent.length).min(length);
+            let start = result.len();
+            result.resize(end as usize, 0);
+            self.streams[extent.stream]
+                .read_exact_at(&mut result[start..], extent.position)
+                .with_context(|| format!("could not read data of object {id}"))?;
+        }
+        if (result.len() as u64) < length {
+            bail!("object {id} holds less than {length} bytes");
+        }
+        Ok(result)
+    }
+
+    /// Write the first `length` bytes of the data of the object to the file, leaving holes.
+    pub(super) fn copy_to(&self, id: u64, length: u64, target: &File) -> Result<()> {
+        let mut buffer = vec![];
+        for (offset, extent) in self.object(id)?.blocks.range(..length) {
+            buffer.resize((extent.length.min(length - offset)) as usize, 0);
+            self.streams[extent.stream]
+                .read_exact_at(&mut buffer, extent.position)
+                .with_context(|| format!("could not read data of object {id}"))?;
+            target.write_all_at(&buffer, *offset)?;
+        }
+        target.set_len(length)?;
+        Ok(())
+    }
+}
+
+fn skip(reader: &mut BufReader<File>, length: u64) -> Result<()> {
+    let length = i64::try_from(length).context("invalid payload length")?;
+    reader.seek_relative(length)?;
+    Ok(())
+}
@@ -0,0 +1,171 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use chrono::{Local, NaiveDateTime, TimeZone};
+use path_absolutize::Absolutize;
+
+use super::mountinfo::Mount;
+use super::naming::NamingSchemes;
+use super::provider::{SnapshotProvider, Volume};
+use super::snapshot::Snapshot;
+use super::snapshotpath::SnapshotPath;
+
+/// The inode number of the root directory of every btrfs subvolume.
+const SUBVOLUME_ROOT_INODE: u64 = 256;
+
+/// Snapshots of btrfs subvolumes taken by snapper, found in `.snapshots/<number>/snapshot` of the
+/// subvolume and described by `.snapshots/<number>/info.xml`.
+#[derive(Debug)]
+pub(crate) struct SnapperProvider;
+
+impl SnapshotProvider for SnapperProvider {
+    /// Find the subvolume containing the path. A path inside `.snapshots/<number>/snapshot`
+    /// belongs to the subvolume containing the snapshot directory.
+    fn find_volume(&self, path: &Path) -> Result<(Volume, PathBuf)> {
+        if let Some(snapshot_path) = SnapshotPath::parse(path) {
+            if snapshot_path.relative.as_os_str().is_empty() {
+                bail!("path points to the snapshot itself, not to a file inside of it");
+            }
+            let live_path = snapshot_path.mountpoint.join(&snapshot_path.relative);
+            return Ok((volume(&snapshot_path.mountpoint), live_path));
+        }
+
+        let filepath = path
+            .absolutize()
+            .with_context(|| format!("could not resolve filepath {path:?}"))?
+            .to_path_buf();
+        let mounts = Mount::read_all()?;
+        let mount = match Mount::containing(&mounts, &filepath) {
+            Some(mount) if mount.fstype == "btrfs" => mount,
+            _ => bail!("file does not reside on a btrfs filesystem"),
+        };
+
+        // the file and some of its parents may be deleted
+        let subvolume = filepath
+            .ancestors()
+            .take_while(|p| p.starts_with(&mount.mount_point))
+            .find(|p| {
+                p.symlink_metadata()
+                    .is_ok_and(|m| m.is_dir() && m.ino() == SUBVOLUME_ROOT_INODE)
+            })
+            .unwrap_or(&mount.mount_point);
+        Ok((volume(subvolume), filepath))
+    }
+
+    fn list_snapshots(&self, volume: &Volume, _schemes: &NamingSchemes) -> Result<Vec<Snapshot>> {
+        let dir = volume.path.join(".snapshots");
+        let mut result = vec![];
+        for entry in dir
+            .read_dir()
+            .with_context(|| format!("could not read snapper dir {dir:?}"))?
+        {
+            let entry = entry.with_context(|| format!("could not read {dir:?}"))?;
+            let Some(number) = entry.file_name().to_str().map(str::to_owned) else {
+                continue;
+            };
+            let path = entry.path().join("snapshot");
+            if number.parse::<u64>().is_err() || !path.is_dir() {
+                continue;
+            }
+
+            let info_path = entry.path().join("info.xml");
+            let info = match fs::read_to_string(&info_path) {
+                Ok(info) => info,
+                // snapshots without metadata are still listed
+                Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+                Err(e) => return Err(e).with_context(|| format!("could not read {info_path:?}")),
+            };
+            let time = xml_value(&info, "date")
+                .and_then(|d| NaiveDateTime::parse_from_str(&d, "%Y-%m-%d %H:%M:%S").ok())
+                // snapper writes UTC
+                .map(|t| Local.from_utc_datetime(&t).naive_local());
+            let class = xml_value(&info, "cleanup").filter(|c| !c.is_empty());
+            let description = xml_value(&info, "description").filter(|d| !d.is_empty());
+            result.push(Snapshot::with_details(
+                path,
+                number,
+                time,
+                class,
+                description,
+            ));
+        }
+        Ok(result)
+    }
+}
+
+fn volume(path: &Path) -> Volume {
+    Volume {
+        name: path.display().to_string(),
+        path: path.to_path_buf(),
+    }
+}
+
+/// Get the text of the first element with the tag. This is sufficient for the flat structure of
+/// `info.xml`.
+fn xml_value(xml: &str, tag: &str) -> Option<String> {
+    let start = xml.find(&format!("<{tag}>"))? + tag.len() + 2;
+    let length = xml[start..].find(&format!("</{tag}>"))?;
+    let value = xml[start..start + length]
+        .trim()
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&");
+    Some(value)
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::{xml_value, SnapperProvider};
+    use crate::zfs::provider::{SnapshotProvider, Volume};
+    use crate::zfs::NamingSchemes;
+
+    #[test]
+    fn list_snapper_snapshots() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        for number in ["1", "2"] {
+            fs::create_dir_all(root.join(".snapshots").join(number).join("snapshot")).unwrap();
+        }
+        fs::write(
+            root.join(".snapshots/2/info.xml"),
+            "<?xml version=\"1.0\"?>\n<snapshot>\n  <type>pre</type>\n  <num>2</num>\n  \
+             <date>2022-11-14 07:30:00</date>\n  <description>zypp(zypper) &amp; more\
+             </description>\n  <cleanup>number</cleanup>\n</snapshot>\n",
+        )
+        .unwrap();
+        fs::create_dir_all(root.join(".snapshots/not-a-number/snapshot")).unwrap();
+
+        let volume = Volume {
+            name: String::new(),
+            path: root.to_path_buf(),
+        };
+        let mut snapshots = SnapperProvider
+            .list_snapshots(&volume, &NamingSchemes::default())
+            .unwrap();
+        snapshots.sort_unstable();
+
+        assert_eq!(snapshots.len(), 2);
+        assert_eq!(snapshots[0].name(), "1");
+        assert_eq!(snapshots[0].time(), None);
+        assert_eq!(snapshots[1].name(), "2");
+        assert!(snapshots[1].time().is_some());
+        assert_eq!(snapshots[1].class(), Some("number"));
+        assert_eq!(snapshots[1].description(), Some("zypp(zypper) & more"));
+        assert_eq!(snapshots[1].path(), root.join(".snapshots/2/snapshot"));
+
+        assert_eq!(xml_value("<a>1</a>", "b"), None);
+
+        // an unreadable info.xml is an error, unlike a missing one
+        fs::create_dir(root.join(".snapshots/1/info.xml")).unwrap();
+        assert!(SnapperProvider
+            .list_snapshots(&volume, &NamingSchemes::default())
+            .is_err());
+    }
+}
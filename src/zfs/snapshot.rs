@@ -2,12 +2,25 @@ use std::fmt::Display;
 use std::path::Path;
 use std::path::PathBuf;
 
-use super::fileinfo::FileInfo;
-use anyhow::{anyhow, Context, Result};
+use chrono::{Local, NaiveDateTime, TimeZone};
 
-#[derive(PartialEq, PartialOrd, Eq, Ord, Debug)]
+use super::naming::NamingSchemes;
+
+/// A snapshot of a dataset. Snapshots are ordered by the time parsed from their name, falling back
+/// to alphabetical order.
+#[derive(PartialEq, PartialOrd, Eq, Ord, Debug, Clone)]
 pub(crate) struct Snapshot {
+    time: Option<NaiveDateTime>,
     path: PathBuf,
+    name: String,
+    class: Option<String>,
+    /// A description given when the snapshot was taken.
+    description: Option<String>,
+    /// The dataset the snapshot belongs to, if it is an origin or a replica of the dataset being
+    /// searched.
+    dataset: Option<String>,
+    /// The time the snapshot was created in UTC, if `zfs` was asked for it.
+    created: Option<NaiveDateTime>,
 }
 
 impl Display for Snapshot {
@@ -17,13 +30,35 @@ impl Display for Snapshot {
 }
 
 impl Snapshot {
-    /// Check if the file is contained in the snapshot. Return its full path if found.
-    pub(crate) fn contains_file(&self, path: &Path) -> Option<PathBuf> {
-        let actual = self.join(path);
-        if actual.exists() {
-            Some(actual)
-        } else {
-            None
+    /// Create the snapshot located at `path`, parsing its name with the given schemes.
+    pub(crate) fn new(path: PathBuf, schemes: &NamingSchemes) -> Self {
+        let name = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or_default()
+            .to_owned();
+        let (class, time) = schemes
+            .parse(&name)
+            .map_or((None, None), |p| (p.class, Some(p.time)));
+        Self::with_details(path, name, time, class, None)
+    }
+
+    /// Create a snapshot whose name and time are not taken from its path, like those of snapper.
+    pub(crate) fn with_details(
+        path: PathBuf,
+        name: String,
+        time: Option<NaiveDateTime>,
+        class: Option<String>,
+        description: Option<String>,
+    ) -> Self {
+        Self {
+            time,
+            path,
+            name,
+            class,
+            description,
+            dataset: None,
+            created: None,
         }
     }
 
@@ -31,35 +66,53 @@ impl Snapshot {
         &self.path
     }
 
-    pub(crate) fn join(&self, path: &Path) -> PathBuf {
-        self.path.clone().join(path)
+    /// The name of the snapshot, which usually is the name of its directory.
+    pub(crate) fn name(&self) -> &str {
+        &self.name
     }
 
-    pub(super) fn get_file_information(&self, file: &Path) -> Result<FileInfo> {
-        let file_absolute = self.path.join(file);
-        let result = file_absolute
-            .parent()
-            .context("must have a parent")?
-            .read_dir()
-            .context("reading dir")?
-            .find(|f| {
-                f.as_ref()
-                    .expect("we have permission to read the file")
-                    .file_name()
-                    == file_absolute
-                        .file_name()
-                        .expect("path ends in proper name, not '..'")
-            })
-            .ok_or_else(|| anyhow!("could not find file that should be there"))??
-            .metadata()?
-            .into();
-
-        Ok(result)
+    pub(crate) fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    /// The frequency class encoded in the snapshot name, like `hourly` or `daily`.
+    pub(crate) fn class(&self) -> Option<&str> {
+        self.class.as_deref()
+    }
+
+    /// The name of the dataset the snapshot belongs to, if it is not the searched dataset but its
+    /// origin or a replica.
+    pub(crate) fn dataset(&self) -> Option<&str> {
+        self.dataset.as_deref()
+    }
+
+    pub(super) fn set_dataset(&mut self, dataset: &str) {
+        self.dataset = Some(dataset.to_owned());
+    }
+
+    /// The time encoded in the snapshot name.
+    pub(crate) fn time(&self) -> Option<NaiveDateTime> {
+        self.time
     }
-}
 
-impl From<PathBuf> for Snapshot {
-    fn from(path: PathBuf) -> Self {
-        Self { path }
+    /// The creation time of the snapshot in UTC, if known.
+    pub(crate) fn created(&self) -> Option<NaiveDateTime> {
+        self.created
+    }
+
+    /// When the snapshot was taken in local time: its creation time if known, else the time in its
+    /// name.
+    pub(crate) fn taken(&self) -> Option<NaiveDateTime> {
+        self.created
+            .map(|t| Local.from_utc_datetime(&t).naive_local())
+            .or(self.time)
+    }
+
+    pub(super) fn set_created(&mut self, time: NaiveDateTime) {
+        self.created = Some(time);
+    }
+
+    pub(crate) fn join(&self, path: &Path) -> PathBuf {
+        self.path.clone().join(path)
     }
 }
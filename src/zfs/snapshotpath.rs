@@ -0,0 +1,75 @@
+use std::path::{Component, Path, PathBuf};
+
+/// A path that points into the snapshot directory of a dataset, like
+/// `/home/.zfs/snapshot/<snapshot>/alice/doc.txt`, or into a snapper snapshot, like
+/// `/home/.snapshots/<number>/snapshot/alice/doc.txt`.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct SnapshotPath {
+    /// The mountpoint of the dataset, `/home`.
+    pub(crate) mountpoint: PathBuf,
+    /// The name of the snapshot.
+    pub(crate) snapshot: String,
+    /// The path relative to the snapshot and dataset, `alice/doc.txt`.
+    pub(crate) relative: PathBuf,
+}
+
+impl SnapshotPath {
+    /// Split the path if it contains `.zfs/snapshot/<snapshot>` or
+    /// `.snapshots/<number>/snapshot`. Return `None` for any other path.
+    pub(crate) fn parse(path: &Path) -> Option<Self> {
+        let components: Vec<_> = path.components().collect();
+        let is = |c: &Component, name: &str| *c == Component::Normal(name.as_ref());
+        let zfs = components.windows(3).position(|w| {
+            is(&w[0], ".zfs") && is(&w[1], "snapshot") && matches!(w[2], Component::Normal(_))
+        });
+        let snapper = components.windows(3).position(|w| {
+            is(&w[0], ".snapshots") && matches!(w[1], Component::Normal(_)) && is(&w[2], "snapshot")
+        });
+        let (position, name) = match (zfs, snapper) {
+            (Some(position), _) => (position, position + 2),
+            (None, Some(position)) => (position, position + 1),
+            (None, None) => return None,
+        };
+
+        Some(Self {
+            mountpoint: components[..position].iter().collect(),
+            snapshot: components[name].as_os_str().to_str()?.to_owned(),
+            relative: components[position + 3..].iter().collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::{Path, PathBuf};
+
+    use super::SnapshotPath;
+
+    #[test]
+    fn parse_snapshot_path() {
+        let parsed =
+            SnapshotPath::parse(Path::new("/home/.zfs/snapshot/znap_weekly/alice/doc.txt"))
+                .unwrap();
+        assert_eq!(
+            parsed,
+            SnapshotPath {
+                mountpoint: PathBuf::from("/home"),
+                snapshot: "znap_weekly".to_owned(),
+                relative: PathBuf::from("alice/doc.txt"),
+            }
+        );
+
+        let parsed = SnapshotPath::parse(Path::new("/.zfs/snapshot/s1/etc/fstab")).unwrap();
+        assert_eq!(parsed.mountpoint, PathBuf::from("/"));
+        assert_eq!(parsed.relative, PathBuf::from("etc/fstab"));
+
+        let parsed =
+            SnapshotPath::parse(Path::new("/home/.snapshots/42/snapshot/alice/doc.txt")).unwrap();
+        assert_eq!(parsed.mountpoint, PathBuf::from("/home"));
+        assert_eq!(parsed.snapshot, "42");
+        assert_eq!(parsed.relative, PathBuf::from("alice/doc.txt"));
+
+        assert_eq!(SnapshotPath::parse(Path::new("/home/alice/doc.txt")), None);
+        assert_eq!(SnapshotPath::parse(Path::new("/home/.zfs/snapshot")), None);
+    }
+}
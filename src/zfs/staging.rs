@@ -0,0 +1,58 @@
+use std::cell::OnceCell;
+use std::fs::{self, Permissions};
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use tempfile::TempDir;
+
+/// A private temporary directory that files are extracted into, to be searched like snapshots.
+/// It is removed when dropped.
+#[derive(Debug)]
+pub(super) struct Staging {
+    dir: TempDir,
+}
+
+impl Staging {
+    /// Create the directory with a random name, accessible only by the current user.
+    pub(super) fn create() -> Result<Self> {
+        let dir = tempfile::Builder::new()
+            .prefix("zfs-undelete-")
+            .tempdir()
+            .context("could not create a temporary directory")?;
+        Ok(Self { dir })
+    }
+
+    /// Get the directory in the cell, creating it first if needed.
+    pub(super) fn get_or_create(cell: &OnceCell<Self>) -> Result<&Self> {
+        if let Some(staging) = cell.get() {
+            return Ok(staging);
+        }
+        let staging = Self::create()?;
+        Ok(cell.get_or_init(|| staging))
+    }
+
+    pub(super) fn path(&self) -> &Path {
+        self.dir.path()
+    }
+}
+
+impl Drop for Staging {
+    /// Remove the directory, including read-only directories extracted into it.
+    fn drop(&mut self) {
+        fn make_writable(path: &Path) {
+            let Ok(metadata) = path.symlink_metadata() else {
+                return;
+            };
+            if metadata.is_dir() {
+                let _ = fs::set_permissions(path, Permissions::from_mode(0o700));
+                for entry in path.read_dir().into_iter().flatten().flatten() {
+                    make_writable(&entry.path());
+                }
+            }
+        }
+
+        // the directory itself is removed by `TempDir` afterwards
+        make_writable(self.dir.path());
+    }
+}
@@ -0,0 +1,465 @@
+use std::cell::OnceCell;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Local};
+
+use super::naming::NamingSchemes;
+use super::provider::{SnapshotProvider, Volume};
+use super::sendstream::{Object, ObjectSet, StreamHeader};
+use super::snapshot::Snapshot;
+use super::staging::Staging;
+use super::zpl::Filesystem;
+
+/// A received snapshot that pending incremental streams are based on. Clones share the objects.
+#[derive(Clone)]
+struct Received {
+    objects: ObjectSet,
+    /// The objects of the file to restore, if it exists in the snapshot.
+    tree: Option<Vec<(u64, Rc<Object>)>>,
+    /// Where the file was extracted to.
+    path: PathBuf,
+}
+
+/// Snapshots kept as `zfs send` stream files in `stream_dir`, each a full stream or an
+/// incremental one on top of another stream in the directory. The file is extracted from each
+/// snapshot into a private staging directory, which is searched like a snapshot.
+#[derive(Debug)]
+pub(crate) struct SendStreamProvider {
+    root: PathBuf,
+    stream_dir: PathBuf,
+    /// The path of the file to restore, relative to `root`.
+    relative: PathBuf,
+    staging: OnceCell<Staging>,
+}
+
+impl SendStreamProvider {
+    /// Parse a mapping like `/home=/mnt/offsite/home` for the absolute path of the file to
+    /// restore.
+    pub(crate) fn parse(mapping: &str, path: &Path) -> Result<Self> {
+        let (root, stream_dir) = mapping
+            .split_once('=')
+            .with_context(|| format!("send streams '{mapping}' are not <path>=<directory>"))?;
+        let root = PathBuf::from(root);
+        if !root.is_absolute() || !Path::new(stream_dir).is_absolute() {
+            bail!("send streams '{mapping}' must map an absolute path to an absolute path");
+        }
+
+        Ok(Self {
+            relative: path.strip_prefix(&root).unwrap_or(path).to_path_buf(),
+            root,
+            stream_dir: stream_dir.into(),
+            staging: OnceCell::new(),
+        })
+    }
+
+    pub(crate) fn contains(&self, path: &Path) -> bool {
+        path.starts_with(&self.root)
+    }
+
+    /// The number of components of `root`, to prefer nested mappings.
+    pub(crate) fn specificity(&self) -> usize {
+        self.root.components().count()
+    }
+
+    /// Read the headers of all streams in `stream_dir`, skipping other files and the streams of
+    /// other datasets than the oldest full stream.
+    fn read_headers(&self) -> Result<Vec<StreamHeader>> {
+        let mut result = vec![];
+        for entry in self
+            .stream_dir
+            .read_dir()
+            .with_context(|| format!("could not read stream dir {:?}", self.stream_dir))?
+        {
+            let path = entry?.path();
+            if path.is_file() {
+                result.extend(StreamHeader::read(&path)?);
+            }
+        }
+        result.sort_unstable_by_key(|h| h.creation_time);
+
+        let Some(first) = result.iter().find(|h| h.from_guid == 0) else {
+            return Ok(result);
+        };
+        let dataset = first.dataset_name().to_owned();
+        result.retain(|header| {
+            let same = header.dataset_name() == dataset;
+            if !same {
+                eprintln!(
+                    "skipping {:?}, it holds {} instead of a snapshot of {dataset}",
+                    header.path, header.to_name
+                );
+            }
+            same
+        });
+        Ok(result)
+    }
+
+    /// Receive the stream, on top of the snapshot it is based on if it is incremental. The file
+    /// is extracted into `staging`, unless none of its objects changed since that snapshot.
+    fn receive(
+        &self,
+        staging: &Path,
+        header: &StreamHeader,
+        source: Option<Received>,
+    ) -> Result<Received> {
+        let (objects, previous) = match source {
+            Some(mut source) => {
+                source.objects.apply(header)?;
+                (source.objects, Some((source.tree, source.path)))
+            }
+            None => (ObjectSet::receive(header)?, None),
+        };
+
+        let filesystem = Filesystem::open(&objects)?;
+        let id = filesystem.lookup(&self.relative)?;
+        let tree = id.map(|id| filesystem.tree(id)).transpose()?;
+        let path = match previous {
+            Some((previous_tree, path)) if previous_tree == tree => path,
+            _ => {
+                let path = staging.join(format!("{:016x}", header.to_guid));
+                fs::create_dir(&path).with_context(|| format!("could not create {path:?}"))?;
+                if let Some(id) = id {
+                    let target = path.join(&self.relative);
+                    if let Some(parent) = target.parent() {
+                        fs::create_dir_all(parent)?;
+                    }
+                    filesystem.extract(id, &target)?;
+                }
+                path
+            }
+        };
+        Ok(Received {
+            objects,
+            tree,
+            path,
+        })
+    }
+
+    /// Describe the snapshot in the stream, whose file was extracted to `path`.
+    fn snapshot(header: &StreamHeader, path: PathBuf, schemes: &NamingSchemes) -> Snapshot {
+        let name = header.snapshot_name().to_owned();
+        let (class, time) = match schemes.parse(&name) {
+            Some(parsed) => (parsed.class, Some(parsed.time)),
+            None => (
+                None,
+                DateTime::from_timestamp(header.creation_time, 0)
+                    .map(|t| t.with_timezone(&Local).naive_local()),
+            ),
+        };
+        let mut snapshot = Snapshot::with_details(path, name, time, class, None);
+        snapshot.set_dataset(header.dataset_name());
+        snapshot
+    }
+}
+
+impl SnapshotProvider for SendStreamProvider {
+    fn find_volume(&self, path: &Path) -> Result<(Volume, PathBuf)> {
+        if !path.starts_with(&self.root) {
+            bail!("file does not reside under {:?}", self.root);
+        }
+        let volume = Volume {
+            name: self.root.display().to_string(),
+            path: self.root.clone(),
+        };
+        Ok((volume, path.to_path_buf()))
+    }
+
+    /// Receive the full streams, then the incremental streams whose source has been received,
+    /// until none are left. A received snapshot is kept only while pending streams are based on
+    /// it, and the last of them is applied to it in place. Snapshots in which the file did not
+    /// change share the directory it was extracted to.
+    fn list_snapshots(&self, _volume: &Volume, schemes: &NamingSchemes) -> Result<Vec<Snapshot>> {
+        let mut pending = self.read_headers()?;
+        let staging = Staging::get_or_create(&self.staging)?.path();
+
+        let mut received: HashMap<u64, Received> = HashMap::new();
+        let mut result = vec![];
+        while let Some(index) = pending
+            .iter()
+            .position(|h| h.from_guid == 0 || received.contains_key(&h.from_guid))
+        {
+            let header = pending.remove(index);
+            let source = if pending.iter().any(|h| h.from_guid == header.from_guid) {
+                received.get(&header.from_guid).cloned()
+            } else {
+                received.remove(&header.from_guid)
+            };
+            let snapshot = self
+                .receive(staging, &header, source)
+                .with_context(|| format!("could not read {}", header.to_name))?;
+            result.push(Self::snapshot(&header, snapshot.path.clone(), schemes));
+            if pending.iter().any(|h| h.from_guid == header.to_guid) {
+                received.insert(header.to_guid, snapshot);
+            }
+        }
+
+        for header in pending {
+            eprintln!(
+                "skipping {:?}, the stream it is based on is missing",
+                header.path
+            );
+        }
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::path::Path;
+
+    use super::SendStreamProvider;
+    use crate::zfs::provider::SnapshotProvider;
+    use crate::zfs::zap::build;
+    use crate::zfs::NamingSchemes;
+
+    const MODE: u64 = 5;
+    const SIZE: u64 = 6;
+    const MTIME: u64 = 1;
+    const UID: u64 = 12;
+    const GID: u64 = 13;
+    const SYMLINK: u64 = 17;
+
+    /// Write the records of a stream.
+    #[derive(Default)]
+    struct Stream(Vec<u8>);
+
+    impl Stream {
+        fn record(&mut self, record_type: u32, fields: &[(usize, &[u8])], payload: &[u8]) {
+            let mut record = vec![0; 312];
+            record[..4].copy_from_slice(&record_type.to_le_bytes());
+            for (offset, bytes) in fields {
+                record[8 + offset..][..bytes.len()].copy_from_slice(bytes);
+            }
+            self.0.extend(record);
+            self.0.extend(payload);
+        }
+
+        fn begin(mut self, name: &str, to_guid: u64, from_guid: u64) -> Self {
+            let fields: &[(usize, &[u8])] = &[
+                (0, &0x2F5BACBACu64.to_le_bytes()),
+                (8, &1u64.to_le_bytes()),
+                (16, &1668411000u64.to_le_bytes()),
+                (24, &2u32.to_le_bytes()),
+                (32, &to_guid.to_le_bytes()),
+                (40, &from_guid.to_le_bytes()),
+                (48, name.as_bytes()),
+            ];
+            self.record(0, fields, &[]);
+            self
+        }
+
+        fn object(mut self, id: u64, bonus_type: u32, block_size: u32, bonus: &[u8]) -> Self {
+            let mut payload = bonus.to_vec();
+            payload.resize(bonus.len().next_multiple_of(8), 0);
+            let fields: &[(usize, &[u8])] = &[
+                (0, &id.to_le_bytes()),
+                (8, &19u32.to_le_bytes()),
+                (12, &bonus_type.to_le_bytes()),
+                (16, &block_size.to_le_bytes()),
+                (20, &(bonus.len() as u32).to_le_bytes()),
+            ];
+            self.record(1, fields, &payload);
+            self
+        }
+
+        fn write(self, id: u64, data: &[u8]) -> Self {
+            self.write_at(id, 0, data)
+        }
+
+        fn write_at(mut self, id: u64, offset: u64, data: &[u8]) -> Self {
+            let fields: &[(usize, &[u8])] = &[
+                (0, &id.to_le_bytes()),
+                (16, &offset.to_le_bytes()),
+                (24, &(data.len() as u64).to_le_bytes()),
+            ];
+            self.record(3, fields, data);
+            self
+        }
+
+        /// An object holding a ZAP, one write per block.
+        fn zap(self, id: u64, data: &[u8]) -> Self {
+            let block_size = if data.len() > 512 {
+                data.len() / 2
+            } else {
+                512
+            };
+            let stream = self.object(id, 0, block_size as u32, &[]);
+            data.chunks(block_size)
+                .enumerate()
+                .fold(stream, |stream, (i, block)| {
+                    stream.write_at(id, (i * block_size) as u64, block)
+                })
+        }
+
+        /// A file, directory or symlink with its system attributes.
+        fn znode(self, id: u64, mode: u64, size: u64, symlink: Option<&str>) -> Self {
+            let layout: u16 = if symlink.is_some() { 3 } else { 2 };
+            let mut bonus = vec![];
+            bonus.extend(0x2F505Au32.to_le_bytes());
+            bonus.extend((layout | 1 << 10).to_le_bytes());
+            bonus.extend((symlink.map_or(0, str::len) as u16).to_le_bytes());
+            for value in [mode, size, 1668411000, 0, 1000, 1000] {
+                bonus.extend(value.to_le_bytes());
+            }
+            if let Some(target) = symlink {
+                bonus.extend(target.as_bytes());
+            }
+            self.object(id, 44, 512, &bonus)
+        }
+
+        fn end(self, path: &Path) {
+            fs::write(path, self.finish()).unwrap();
+        }
+
+        fn finish(mut self) -> Vec<u8> {
+            self.record(5, &[], &[]);
+            self.0
+        }
+    }
+
+    fn dirent(id: u64, dirent_type: u64) -> u64 {
+        id | dirent_type << 60
+    }
+
+    /// A full stream of a filesystem with `docs/report.txt` and `docs/link`, without its END
+    /// record.
+    fn report_stream(name: &str) -> Stream {
+        let registration = |number: u64, length: u64| number | length << 24;
+        let layouts = build::fat(
+            &[("2", &[5, 6, 1, 12, 13]), ("3", &[5, 6, 1, 12, 13, 17])],
+            1024,
+        );
+        Stream::default()
+            .begin(name, 1, 0)
+            .zap(1, &build::micro(&[("ROOT", 34), ("SA_ATTRS", 32)]))
+            .zap(32, &build::micro(&[("REGISTRY", 33), ("LAYOUTS", 35)]))
+            .zap(
+                33,
+                &build::micro(&[
+                    ("ZPL_MODE", registration(MODE, 8)),
+                    ("ZPL_SIZE", registration(SIZE, 8)),
+                    ("ZPL_MTIME", registration(MTIME, 16)),
+                    ("ZPL_UID", registration(UID, 8)),
+                    ("ZPL_GID", registration(GID, 8)),
+                    ("ZPL_SYMLINK", registration(SYMLINK, 0)),
+                ]),
+            )
+            // a fat ZAP with a hole between its header and its leaf
+            .object(35, 0, 1024, &[])
+            .write_at(35, 0, &layouts[..1024])
+            .write_at(35, 4 * 1024, &layouts[1024..])
+            .zap(34, &build::micro(&[("docs", dirent(36, 4))]))
+            .znode(34, 0o40755, 3, None)
+            .zap(
+                36,
+                &build::micro(&[("report.txt", dirent(37, 8)), ("link", dirent(38, 10))]),
+            )
+            .znode(36, 0o40555, 4, None)
+            .znode(37, 0o100640, 11, None)
+            .write(37, b"first draft")
+            .znode(38, 0o120777, 10, Some("report.txt"))
+    }
+
+    #[test]
+    fn restore_from_full_and_incremental_streams() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+
+        report_stream("tank/srv@first").end(&root.join("first.zfs"));
+        Stream::default()
+            .begin("tank/srv@second", 2, 1)
+            .znode(37, 0o100640, 14, None)
+            .write(37, b"second version")
+            .end(&root.join("second.zfs"));
+        Stream::default()
+            .begin("tank/srv@third", 3, 2)
+            .end(&root.join("third.zfs"));
+        Stream::default()
+            .begin("tank/srv@orphan", 5, 4)
+            .end(&root.join("orphan.zfs"));
+        Stream::default()
+            .begin("tank/other@first", 6, 0)
+            .end(&root.join("other.zfs"));
+        fs::write(root.join("notes.txt"), "not a stream").unwrap();
+
+        let file = Path::new("/srv/docs/report.txt");
+        let provider =
+            SendStreamProvider::parse(&format!("/srv={}", root.display()), file).unwrap();
+        let (volume, _) = provider.find_volume(file).unwrap();
+        let mut snapshots = provider
+            .list_snapshots(&volume, &NamingSchemes::default())
+            .unwrap();
+        snapshots.sort_unstable_by_key(|s| s.name().to_owned());
+
+        assert_eq!(snapshots.len(), 3);
+        assert_eq!(snapshots[0].name(), "first");
+        assert_eq!(snapshots[0].dataset(), Some("tank/srv"));
+        assert!(snapshots[0].time().is_some());
+        let relative = Path::new("docs/report.txt");
+        assert_eq!(
+            fs::read_to_string(snapshots[0].join(relative)).unwrap(),
+            "first draft"
+        );
+        assert_eq!(
+            fs::read_to_string(snapshots[1].join(relative)).unwrap(),
+            "second version"
+        );
+        // the file did not change, so it was not extracted again
+        assert_eq!(snapshots[2].path(), snapshots[1].path());
+
+        let directory = Path::new("/srv/docs");
+        let provider =
+            SendStreamProvider::parse(&format!("/srv={}", root.display()), directory).unwrap();
+        let snapshots = provider
+            .list_snapshots(&volume, &NamingSchemes::default())
+            .unwrap();
+        let link = snapshots[0].join(Path::new("docs/link"));
+        assert_eq!(fs::read_link(link).unwrap(), Path::new("report.txt"));
+        drop(provider);
+        assert!(!snapshots[0].path().exists());
+    }
+
+    #[test]
+    fn reject_truncated_and_corrupt_streams() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let report = || report_stream("tank/srv@first");
+
+        let mut truncated = report().finish();
+        truncated.truncate(truncated.len() - 100);
+        let mut huge_bonus = report();
+        huge_bonus.record(1, &[(0, &39u64.to_le_bytes()), (28, &[0xff; 4])], &[]);
+        let beyond_end = report().write_at(36, u64::MAX - 7, &[0; 8]);
+        let oversized_block = report().write(37, &[0; 600]);
+        let cycle = report().write(
+            36,
+            &build::micro(&[("report.txt", dirent(37, 8)), ("up", dirent(34, 4))]),
+        );
+
+        for (name, stream) in [
+            ("truncated", truncated),
+            ("huge_bonus", huge_bonus.finish()),
+            ("beyond_end", beyond_end.finish()),
+            ("oversized_block", oversized_block.finish()),
+            ("cycle", cycle.finish()),
+        ] {
+            let stream_dir = root.join(name);
+            fs::create_dir(&stream_dir).unwrap();
+            fs::write(stream_dir.join("first.zfs"), stream).unwrap();
+            let mapping = format!("/srv={}", stream_dir.display());
+            let provider = SendStreamProvider::parse(&mapping, Path::new("/srv/docs")).unwrap();
+            let (volume, _) = provider.find_volume(Path::new("/srv/docs")).unwrap();
+            assert!(
+                provider
+                    .list_snapshots(&volume, &NamingSchemes::default())
+                    .is_err(),
+                "{name}"
+            );
+        }
+    }
+}
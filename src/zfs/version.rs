@@ -0,0 +1,348 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use anyhow::{bail, Context, Result};
+use sha2::{Digest, Sha256};
+
+use super::fileinfo::{FileInfo, FileType};
+use super::fingerprint::{Changes, Fingerprint};
+use super::snapshot::Snapshot;
+
+/// How to decide whether the file in two snapshots is the same version.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Dedup {
+    /// Compare `st_mtime` and `st_size`.
+    #[default]
+    Metadata,
+    /// Compare the contents of the files.
+    Content,
+}
+
+impl FromStr for Dedup {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "metadata" => Ok(Self::Metadata),
+            "content" => Ok(Self::Content),
+            _ => bail!("unknown dedup mode '{s}', expected 'metadata' or 'content'"),
+        }
+    }
+}
+
+/// A unique version of a file and all snapshots containing it, in chronological order.
+#[derive(Debug)]
+pub(crate) struct Version<'a> {
+    snapshots: Vec<&'a Snapshot>,
+    info: FileInfo,
+    changes: Option<Changes>,
+}
+
+impl<'a> Version<'a> {
+    /// The snapshot the version is restored from, which is the oldest one containing it.
+    pub(crate) fn snapshot(&self) -> &'a Snapshot {
+        self.snapshots[0]
+    }
+
+    /// All snapshots containing this version, oldest first.
+    pub(crate) fn snapshots(&self) -> &[&'a Snapshot] {
+        &self.snapshots
+    }
+
+    /// The metadata of the file in the snapshot it is restored from.
+    pub(crate) fn info(&self) -> &FileInfo {
+        &self.info
+    }
+
+    /// For a directory, the changes made by the version that followed it.
+    pub(crate) fn changes(&self) -> Option<&Changes> {
+        self.changes.as_ref()
+    }
+}
+
+/// The property of a file that is equal for all snapshots containing the same version.
+/// Directories have none, they are compared with their neighbours instead.
+#[derive(Debug, PartialEq, Eq, Hash)]
+enum VersionKey {
+    Metadata(SystemTime, usize),
+    /// The size and, if another version has the same size, the hash of the content.
+    Content(usize, Option<[u8; 32]>),
+    /// The target of a symlink.
+    Symlink(Option<PathBuf>),
+}
+
+/// The fingerprints of the directories in the snapshots, taken when first needed. A directory that
+/// cannot be read has none, which is reported once.
+struct Fingerprints<'i, 'a> {
+    infos: &'i [(&'a Snapshot, PathBuf, FileInfo)],
+    with_hashes: bool,
+    taken: Vec<Option<Option<Fingerprint>>>,
+}
+
+impl<'i, 'a> Fingerprints<'i, 'a> {
+    fn new(infos: &'i [(&'a Snapshot, PathBuf, FileInfo)], dedup: Dedup) -> Self {
+        Self {
+            infos,
+            with_hashes: dedup == Dedup::Content,
+            taken: infos.iter().map(|_| None).collect(),
+        }
+    }
+
+    fn get(&mut self, index: usize) -> Option<&Fingerprint> {
+        let (path, with_hashes) = (&self.infos[index].1, self.with_hashes);
+        self.taken[index]
+            .get_or_insert_with(|| match Fingerprint::of_directory(path, with_hashes) {
+                Ok(fingerprint) => Some(fingerprint),
+                Err(e) => {
+                    eprintln!("treating {path:?} as changed: {e:#}");
+                    None
+                }
+            })
+            .as_ref()
+    }
+
+    /// Whether two directories with the same top-level metadata have the same contents. If either
+    /// cannot be read, they are taken to differ.
+    fn same_contents(&mut self, a: usize, b: usize) -> bool {
+        let (a_info, b_info) = (&self.infos[a].2, &self.infos[b].2);
+        let same_metadata = a_info.file_type == FileType::Directory
+            && b_info.file_type == FileType::Directory
+            && (
+                a_info.mtime,
+                a_info.size,
+                a_info.mode,
+                a_info.uid,
+                a_info.gid,
+            ) == (
+                b_info.mtime,
+                b_info.size,
+                b_info.mode,
+                b_info.uid,
+                b_info.gid,
+            );
+        if !same_metadata {
+            return false;
+        }
+        let Some(digest) = self.get(a).map(Fingerprint::digest) else {
+            return false;
+        };
+        self.get(b)
+            .is_some_and(|fingerprint| fingerprint.digest() == digest)
+    }
+
+    /// The changes made between two directories, if both can be read.
+    fn changes(&mut self, old: usize, new: usize) -> Option<Changes> {
+        self.get(old)?;
+        self.get(new)?;
+        let (Some(Some(old)), Some(Some(new))) = (&self.taken[old], &self.taken[new]) else {
+            return None;
+        };
+        Some(new.changes_since(old))
+    }
+}
+
+/// Group the snapshots by version of the file, given with its path inside each snapshot. Versions
+/// are ordered by their first occurrence, which is the same as the order of the snapshots. A
+/// directory is the same version as in the previous snapshot if its top-level metadata is identical
+/// and so is the fingerprint of its contents, so only those are fingerprinted. Each version of a
+/// directory is diffed against the version in the snapshot following it.
+pub(super) fn group_versions<'a>(
+    infos: Vec<(&'a Snapshot, PathBuf, FileInfo)>,
+    dedup: Dedup,
+) -> Result<Vec<Version<'a>>> {
+    let keys = match dedup {
+        Dedup::Metadata => infos
+            .iter()
+            .map(|(_, _, info)| VersionKey::Metadata(info.mtime, info.size))
+            .collect(),
+        Dedup::Content => content_keys(&infos)?,
+    };
+
+    let mut fingerprints = Fingerprints::new(&infos, dedup);
+    let mut versions: Vec<Version> = vec![];
+    let mut version_of_snapshot: Vec<usize> = vec![];
+    let mut index_of_key = HashMap::new();
+    for (i, ((snapshot, _, info), key)) in infos.iter().zip(keys).enumerate() {
+        let mut new_version = || {
+            versions.push(Version {
+                snapshots: vec![],
+                info: info.clone(),
+                changes: None,
+            });
+            versions.len() - 1
+        };
+        let index = if info.file_type != FileType::Directory {
+            *index_of_key.entry(key).or_insert_with(new_version)
+        } else if i > 0 && fingerprints.same_contents(i - 1, i) {
+            version_of_snapshot[i - 1]
+        } else {
+            new_version()
+        };
+        versions[index].snapshots.push(snapshot);
+        version_of_snapshot.push(index);
+    }
+
+    for i in 1..version_of_snapshot.len() {
+        let (version, next) = (version_of_snapshot[i - 1], version_of_snapshot[i]);
+        let both_directories = [i - 1, i]
+            .iter()
+            .all(|&j| infos[j].2.file_type == FileType::Directory);
+        if version != next && both_directories {
+            versions[version].changes = fingerprints.changes(i - 1, i);
+        }
+    }
+    Ok(versions)
+}
+
+/// Compute the content keys of files. Only files whose size is shared with another snapshot are
+/// hashed, since a different size already means a different version.
+fn content_keys(infos: &[(&Snapshot, PathBuf, FileInfo)]) -> Result<Vec<VersionKey>> {
+    let mut count_of_size: HashMap<usize, usize> = HashMap::new();
+    for (_, _, info) in infos {
+        *count_of_size.entry(info.size).or_default() += 1;
+    }
+
+    infos
+        .iter()
+        .map(|(_, path, info)| {
+            match info.file_type {
+                FileType::Regular => (),
+                FileType::Symlink => return Ok(VersionKey::Symlink(info.symlink_target.clone())),
+                // directories are compared with their neighbours instead, special files have no
+                // content that could be read
+                _ => return Ok(VersionKey::Metadata(info.mtime, info.size)),
+            }
+            let hash = if count_of_size[&info.size] > 1 {
+                Some(hash_file(path)?)
+            } else {
+                None
+            };
+            Ok(VersionKey::Content(info.size, hash))
+        })
+        .collect()
+}
+
+pub(super) fn hash_file(path: &Path) -> Result<[u8; 32]> {
+    let mut file = File::open(path).with_context(|| format!("could not open {path:?}"))?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher).with_context(|| format!("could not read {path:?}"))?;
+    Ok(hasher.finalize().into())
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::{self, File};
+    use std::path::Path;
+    use std::time::{Duration, SystemTime};
+
+    use super::{group_versions, Dedup};
+    use crate::zfs::fileinfo::FileInfo;
+    use crate::zfs::fingerprint::Changes;
+    use crate::zfs::naming::NamingSchemes;
+    use crate::zfs::Snapshot;
+
+    #[test]
+    fn group_by_content() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let contents = [("s1", "aaaa", 1), ("s2", "aaaa", 2), ("s3", "bbbb", 2)];
+        let snapshots: Vec<_> = contents
+            .iter()
+            .map(|(name, content, mtime)| {
+                let dir = root.join(name);
+                fs::create_dir_all(&dir).unwrap();
+                fs::write(dir.join("file"), content).unwrap();
+                File::options()
+                    .write(true)
+                    .open(dir.join("file"))
+                    .unwrap()
+                    .set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(*mtime))
+                    .unwrap();
+                Snapshot::new(dir, &NamingSchemes::default())
+            })
+            .collect();
+        let file = Path::new("file");
+        let infos = || {
+            snapshots
+                .iter()
+                .map(|s| (s, s.join(file), FileInfo::read(&s.join(file)).unwrap()))
+                .collect()
+        };
+
+        // `touch` creates a new version, an edit keeping size and mtime is hidden
+        let versions = group_versions(infos(), Dedup::Metadata).unwrap();
+        let names: Vec<Vec<_>> = versions
+            .iter()
+            .map(|v| v.snapshots().iter().map(|s| s.name()).collect())
+            .collect();
+        assert_eq!(names, vec![vec!["s1"], vec!["s2", "s3"]]);
+
+        let versions = group_versions(infos(), Dedup::Content).unwrap();
+        let names: Vec<Vec<_>> = versions
+            .iter()
+            .map(|v| v.snapshots().iter().map(|s| s.name()).collect())
+            .collect();
+        assert_eq!(names, vec![vec!["s1", "s2"], vec!["s3"]]);
+    }
+
+    #[test]
+    fn diff_directories_against_the_next_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let contents: [(&str, &[&str]); 5] = [
+            ("s1", &["a"]),
+            ("s2", &["a"]),
+            ("s3", &["a", "b"]),
+            ("s4", &["a"]),
+            ("s5", &["c"]),
+        ];
+        let set_modified = |path: &Path| {
+            File::open(path)
+                .unwrap()
+                .set_modified(SystemTime::UNIX_EPOCH)
+                .unwrap();
+        };
+        let snapshots: Vec<_> = contents
+            .iter()
+            .map(|(name, files)| {
+                let dir = root.join(name);
+                fs::create_dir_all(dir.join("docs")).unwrap();
+                for file in *files {
+                    let path = dir.join("docs").join(file);
+                    fs::write(&path, "").unwrap();
+                    set_modified(&path);
+                }
+                set_modified(&dir.join("docs"));
+                Snapshot::new(dir, &NamingSchemes::default())
+            })
+            .collect();
+        let file = Path::new("docs");
+        let infos = snapshots
+            .iter()
+            .map(|s| (s, s.join(file), FileInfo::read(&s.join(file)).unwrap()))
+            .collect();
+
+        // only neighbours are merged, so the contents of s4 are a new version
+        let versions = group_versions(infos, Dedup::Metadata).unwrap();
+        let changes: Vec<_> = versions.iter().map(|v| v.changes()).collect();
+        let expected = |added, removed| Changes {
+            added,
+            removed,
+            modified: 0,
+        };
+        assert_eq!(versions[0].snapshots().len(), 2);
+        assert_eq!(
+            changes,
+            [
+                Some(&expected(1, 0)),
+                Some(&expected(0, 1)),
+                Some(&expected(1, 1)),
+                None
+            ]
+        );
+    }
+}
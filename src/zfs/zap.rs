@@ -0,0 +1,247 @@
+use anyhow::{bail, Result};
+
+use super::sendstream::ByteOrder;
+
+const ZBT_LEAF: u64 = 1 << 63;
+const ZBT_HEADER: u64 = (1 << 63) + 1;
+const ZBT_MICRO: u64 = (1 << 63) + 3;
+const ZAP_LEAF_MAGIC: u32 = 0x2AB1EAF;
+
+/// The size of the header and of each entry of a micro ZAP.
+const MZAP_ENTRY_SIZE: usize = 64;
+/// Where the name starts in an entry of a micro ZAP.
+const MZAP_NAME_OFFSET: usize = 14;
+
+/// The size of the header of a leaf of a fat ZAP.
+const LEAF_HEADER_SIZE: usize = 48;
+const LEAF_CHUNK_SIZE: usize = 24;
+const LEAF_ARRAY_BYTES: usize = 21;
+const CHUNK_ENTRY: u8 = 252;
+const CHUNK_ARRAY: u8 = 251;
+
+/// An entry of a ZAP object, with its value widened to 64 bit integers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct ZapEntry {
+    pub(super) name: Vec<u8>,
+    pub(super) value: Vec<u64>,
+}
+
+/// Read all entries of a ZAP object, which is a key-value store used for directories and other
+/// metadata, from its blocks by offset. Small ones are micro ZAPs, larger ones fat ZAPs consisting
+/// of a header block and leaf blocks, possibly with holes in between.
+pub(super) fn read_entries(
+    blocks: &[(u64, Vec<u8>)],
+    block_size: usize,
+    order: ByteOrder,
+) -> Result<Vec<ZapEntry>> {
+    if !block_size.is_power_of_two() || block_size < 512 {
+        bail!("invalid ZAP block size {block_size}");
+    }
+    let first_block = match blocks.first() {
+        Some((0, block)) if block.len() == block_size => block,
+        _ => bail!("ZAP object is empty"),
+    };
+
+    match order.u64(first_block, 0)? {
+        ZBT_MICRO => first_block
+            .chunks_exact(MZAP_ENTRY_SIZE)
+            .skip(1)
+            .filter(|entry| entry[MZAP_NAME_OFFSET] != 0)
+            .map(|entry| {
+                let name = &entry[MZAP_NAME_OFFSET..];
+                let length = name.iter().position(|b| *b == 0).unwrap_or(name.len());
+                Ok(ZapEntry {
+                    name: name[..length].to_vec(),
+                    value: vec![order.u64(entry, 0)?],
+                })
+            })
+            .collect(),
+        // leaves are found by their type instead of through the pointer table
+        ZBT_HEADER => {
+            let mut result = vec![];
+            let leaves = blocks[1..].iter().map(|(_, block)| block);
+            for block in leaves.filter(|block| block.len() == block_size) {
+                if order.u64(block, 0)? == ZBT_LEAF && order.u32(block, 24)? == ZAP_LEAF_MAGIC {
+                    read_leaf(block, order, &mut result)?;
+                }
+            }
+            Ok(result)
+        }
+        _ => bail!("object is not a ZAP"),
+    }
+}
+
+/// Read the entries of a leaf of a fat ZAP. After the header, the leaf has a hash table and then
+/// chunks holding entries and the arrays of their names and values.
+fn read_leaf(block: &[u8], order: ByteOrder, result: &mut Vec<ZapEntry>) -> Result<()> {
+    let hash_entries = block.len() / 32;
+    let chunks_start = LEAF_HEADER_SIZE + 2 * hash_entries;
+    let chunk_count = (block.len() - 2 * hash_entries) / LEAF_CHUNK_SIZE - 2;
+    let chunk = |index: usize| -> Result<&[u8]> {
+        if index >= chunk_count {
+            bail!("invalid chunk {index} in ZAP leaf");
+        }
+        let start = chunks_start + index * LEAF_CHUNK_SIZE;
+        Ok(&block[start..start + LEAF_CHUNK_SIZE])
+    };
+    // arrays are stored big-endian, independent of the byte order
+    let read_array = |mut index: usize, length: usize| -> Result<Vec<u8>> {
+        let mut bytes = Vec::with_capacity(length);
+        while bytes.len() < length {
+            let array = chunk(index)?;
+            if array[0] != CHUNK_ARRAY {
+                bail!("expected an array in chunk {index} of ZAP leaf");
+            }
+            let count = LEAF_ARRAY_BYTES.min(length - bytes.len());
+            bytes.extend_from_slice(&array[1..1 + count]);
+            index = order.u16(array, 1 + LEAF_ARRAY_BYTES)?.into();
+        }
+        Ok(bytes)
+    };
+
+    for index in 0..chunk_count {
+        let entry = chunk(index)?;
+        if entry[0] != CHUNK_ENTRY {
+            continue;
+        }
+        let value_int_length = usize::from(entry[1]);
+        let name_chunk = order.u16(entry, 4)?.into();
+        let name_length = order.u16(entry, 6)?.into();
+        let value_chunk = order.u16(entry, 8)?.into();
+        let value_count = usize::from(order.u16(entry, 10)?);
+        if !matches!(value_int_length, 1 | 2 | 4 | 8) {
+            bail!("invalid integer length {value_int_length} in ZAP leaf");
+        }
+
+        let mut name = read_array(name_chunk, name_length)?;
+        if name.last() == Some(&0) {
+            name.pop();
+        }
+        let value = read_array(value_chunk, value_count * value_int_length)?
+            .chunks_exact(value_int_length)
+            .map(|int| {
+                int.iter()
+                    .fold(0, |value, byte| value << 8 | u64::from(*byte))
+            })
+            .collect();
+        result.push(ZapEntry { name, value });
+    }
+    Ok(())
+}
+
+/// Write ZAP objects the way ZFS does, for testing.
+#[cfg(test)]
+pub(super) mod build {
+    use super::*;
+
+    /// A micro ZAP in a single block of 512 bytes.
+    pub(crate) fn micro(entries: &[(&str, u64)]) -> Vec<u8> {
+        let mut block = vec![0; 512];
+        block[..8].copy_from_slice(&ZBT_MICRO.to_le_bytes());
+        for (index, (name, value)) in entries.iter().enumerate() {
+            let entry = &mut block[(index + 1) * MZAP_ENTRY_SIZE..][..MZAP_ENTRY_SIZE];
+            entry[..8].copy_from_slice(&value.to_le_bytes());
+            entry[MZAP_NAME_OFFSET..][..name.len()].copy_from_slice(name.as_bytes());
+        }
+        block
+    }
+
+    /// A fat ZAP with a header block and a single leaf, each of `block_size` bytes. Values are
+    /// arrays of 2 byte integers.
+    pub(crate) fn fat(entries: &[(&str, &[u16])], block_size: usize) -> Vec<u8> {
+        let mut header = vec![0; block_size];
+        header[..8].copy_from_slice(&ZBT_HEADER.to_le_bytes());
+
+        let mut leaf = vec![0; block_size];
+        leaf[..8].copy_from_slice(&ZBT_LEAF.to_le_bytes());
+        leaf[24..28].copy_from_slice(&ZAP_LEAF_MAGIC.to_le_bytes());
+        let chunks_start = LEAF_HEADER_SIZE + 2 * (block_size / 32);
+        let mut next_chunk = 0;
+        let mut add_array = |leaf: &mut Vec<u8>, bytes: &[u8]| -> u16 {
+            let first = next_chunk;
+            let chunks = bytes.chunks(LEAF_ARRAY_BYTES).collect::<Vec<_>>();
+            for (i, part) in chunks.iter().enumerate() {
+                let chunk = &mut leaf[chunks_start + next_chunk * LEAF_CHUNK_SIZE..];
+                chunk[0] = CHUNK_ARRAY;
+                chunk[1..1 + part.len()].copy_from_slice(part);
+                let next: u16 = if i + 1 == chunks.len() {
+                    0xffff
+                } else {
+                    next_chunk as u16 + 1
+                };
+                chunk[22..24].copy_from_slice(&next.to_le_bytes());
+                next_chunk += 1;
+            }
+            first as u16
+        };
+
+        let mut entry_chunks = vec![];
+        for (name, value) in entries {
+            let mut name = name.as_bytes().to_vec();
+            name.push(0);
+            let name_chunk = add_array(&mut leaf, &name);
+            let value_bytes: Vec<u8> = value.iter().flat_map(|v| v.to_be_bytes()).collect();
+            let value_chunk = add_array(&mut leaf, &value_bytes);
+            entry_chunks.push((name.len(), name_chunk, value.len(), value_chunk));
+        }
+        for (name_length, name_chunk, value_count, value_chunk) in entry_chunks {
+            let chunk = &mut leaf[chunks_start + next_chunk * LEAF_CHUNK_SIZE..];
+            chunk[0] = CHUNK_ENTRY;
+            chunk[1] = 2;
+            chunk[4..6].copy_from_slice(&name_chunk.to_le_bytes());
+            chunk[6..8].copy_from_slice(&(name_length as u16).to_le_bytes());
+            chunk[8..10].copy_from_slice(&value_chunk.to_le_bytes());
+            chunk[10..12].copy_from_slice(&(value_count as u16).to_le_bytes());
+            next_chunk += 1;
+        }
+
+        header.extend(leaf);
+        header
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{build, read_entries, ZapEntry};
+    use crate::zfs::sendstream::ByteOrder;
+
+    /// Split the data of an object into blocks, leaving out the holes.
+    fn blocks(data: &[u8], block_size: usize) -> Vec<(u64, Vec<u8>)> {
+        data.chunks(block_size)
+            .enumerate()
+            .filter(|(_, block)| block.iter().any(|b| *b != 0))
+            .map(|(i, block)| ((i * block_size) as u64, block.to_vec()))
+            .collect()
+    }
+
+    #[test]
+    fn read_micro_and_fat_zaps() {
+        let micro = build::micro(&[("ROOT", 34), ("VERSION", 5)]);
+        assert_eq!(
+            read_entries(&blocks(&micro, 512), 512, ByteOrder::Little).unwrap(),
+            vec![
+                ZapEntry {
+                    name: b"ROOT".to_vec(),
+                    value: vec![34]
+                },
+                ZapEntry {
+                    name: b"VERSION".to_vec(),
+                    value: vec![5]
+                },
+            ]
+        );
+
+        let long_name = "a-name-that-needs-more-than-one-chunk";
+        let mut fat = build::fat(&[("2", &[5, 6, 0x1234]), (long_name, &[1])], 4096);
+        // a hole between the header and the leaf
+        fat.splice(4096..4096, vec![0; 2 * 4096]);
+        let entries = read_entries(&blocks(&fat, 4096), 4096, ByteOrder::Little).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].name, b"2");
+        assert_eq!(entries[0].value, vec![5, 6, 0x1234]);
+        assert_eq!(entries[1].name, long_name.as_bytes());
+
+        assert!(read_entries(&[(0, vec![0; 512])], 512, ByteOrder::Little).is_err());
+        assert!(read_entries(&[], 512, ByteOrder::Little).is_err());
+    }
+}
@@ -0,0 +1,365 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use path_absolutize::Absolutize;
+
+use super::cmd::{DatasetInfo, ZfsCommand};
+use super::encryption::{check_key, KeyNotLoaded};
+use super::mountinfo::Mount;
+use super::naming::NamingSchemes;
+use super::provider::{read_snapshot_dir, SnapshotProvider, Volume};
+use super::snapshot::Snapshot;
+use super::snapshotpath::SnapshotPath;
+
+/// Snapshots of ZFS datasets, found in their `.zfs/snapshot` directory.
+#[derive(Debug)]
+pub(crate) struct ZfsProvider {
+    zfs: ZfsCommand,
+}
+
+impl ZfsProvider {
+    pub(crate) fn new(zfs: ZfsCommand) -> Self {
+        Self { zfs }
+    }
+
+    /// Traverse the absolute path from the child to root and return the first zfs mountpoint. A
+    /// path inside `.zfs/snapshot` belongs to the dataset containing the snapshot directory.
+    fn find_volume_in(&self, mounts: &[Mount], path: &Path) -> Result<(Volume, PathBuf)> {
+        let (mount, live_path) = if let Some(snapshot_path) = SnapshotPath::parse(path) {
+            if snapshot_path.relative.as_os_str().is_empty() {
+                bail!("path points to the snapshot itself, not to a file inside of it");
+            }
+            let mount = find_mount_in(mounts, &snapshot_path.mountpoint)?;
+            if mount.mount_point != snapshot_path.mountpoint {
+                bail!(
+                    "{:?} is not the mountpoint of a ZFS dataset",
+                    snapshot_path.mountpoint
+                );
+            }
+            let live_path = mount.mount_point.join(&snapshot_path.relative);
+            (mount, live_path)
+        } else {
+            let filepath = path
+                .absolutize()
+                .with_context(|| format!("could not resolve filepath {path:?}"))?
+                .to_path_buf();
+            (find_mount_in(mounts, &filepath)?, filepath)
+        };
+
+        self.check_dataset(&mount, &live_path)?;
+        Ok((mount.into(), live_path))
+    }
+
+    /// Check the dataset found in mountinfo with `zfs list`: its name must be a filesystem, and
+    /// a file below the mountpoint of a child dataset that is not mounted is reported, since it
+    /// is only in the snapshots of that child. If the child is not mounted because its key is not
+    /// loaded, fail with `KeyNotLoaded`. `zfs` may not be usable, like inside a container, so the
+    /// check is skipped with a warning then.
+    fn check_dataset(&self, mount: &Mount, path: &Path) -> Result<()> {
+        let datasets = match self.zfs.list_filesystems(&mount.source) {
+            Ok(datasets) => datasets,
+            Err(e) => {
+                eprintln!("could not check dataset {} with zfs: {e:#}", mount.source);
+                return Ok(());
+            }
+        };
+        let Some(dataset) = datasets.iter().find(|d| d.name == mount.source) else {
+            bail!(
+                "{:?} is mounted from {}, which zfs does not list as a filesystem",
+                mount.mount_point,
+                mount.source
+            );
+        };
+
+        if let Some(child) = unmounted_child(mount, dataset, &datasets, path) {
+            if child.keystatus == "unavailable" {
+                return Err(KeyNotLoaded {
+                    dataset: child.name.clone(),
+                    encryption_root: child.encryption_root.clone(),
+                }
+                .into());
+            }
+            eprintln!(
+                "{path:?} is below the mountpoint of {}, which is not mounted, searching {} instead",
+                child.name, mount.source
+            );
+        }
+        Ok(())
+    }
+}
+
+impl SnapshotProvider for ZfsProvider {
+    fn find_volume(&self, path: &Path) -> Result<(Volume, PathBuf)> {
+        self.find_volume_in(&Mount::read_all()?, path)
+    }
+
+    fn is_zfs(&self) -> bool {
+        true
+    }
+
+    fn list_snapshots(&self, volume: &Volume, schemes: &NamingSchemes) -> Result<Vec<Snapshot>> {
+        read_dataset_snapshots(&volume.path, schemes).map_err(|e| {
+            // explain a missing key instead, if `zfs` can tell
+            match check_key(&self.zfs, &volume.name) {
+                Err(locked) if locked.is::<KeyNotLoaded>() => locked,
+                _ => e,
+            }
+        })
+    }
+}
+
+impl From<Mount> for Volume {
+    fn from(mount: Mount) -> Self {
+        Self {
+            name: mount.source,
+            path: mount.mount_point,
+        }
+    }
+}
+
+/// Read the snapshots of the dataset mounted at `mountpoint`.
+pub(super) fn read_dataset_snapshots(
+    mountpoint: &Path,
+    schemes: &NamingSchemes,
+) -> Result<Vec<Snapshot>> {
+    read_snapshot_dir(&mountpoint.join(".zfs/snapshot"), schemes)
+}
+
+/// Find the mount of the dataset with the given name.
+pub(super) fn find_mount_of_dataset(zfs: &ZfsCommand, name: &str) -> Result<Mount> {
+    let mount = Mount::read_all()?
+        .into_iter()
+        .rev()
+        .find(|m| m.fstype == "zfs" && m.source == name && m.root == Path::new("/"));
+    match mount {
+        Some(mount) => Ok(mount),
+        None => {
+            check_key(zfs, name)?;
+            bail!("dataset {name} is not mounted")
+        }
+    }
+}
+
+/// Find the deepest dataset below `dataset` whose mountpoint contains the path. It is not mounted,
+/// or else the path would be on its mount. Mountpoints are taken relative to the mountpoint of
+/// `dataset`, since it may be mounted elsewhere in this mount namespace. Below a dataset with
+/// `mountpoint=legacy`, which is mounted through fstab, only children with a mountpoint of their
+/// own can be located, at exactly that path.
+fn unmounted_child<'a>(
+    mount: &Mount,
+    dataset: &DatasetInfo,
+    datasets: &'a [DatasetInfo],
+    path: &Path,
+) -> Option<&'a DatasetInfo> {
+    let parent_mountpoint = match dataset.mountpoint.as_str() {
+        "legacy" => None,
+        mountpoint if Path::new(mountpoint).is_absolute() => Some(Path::new(mountpoint)),
+        _ => return None,
+    };
+    let prefix = format!("{}/", dataset.name);
+
+    datasets
+        .iter()
+        .filter(|d| d.name.starts_with(&prefix))
+        .filter_map(|d| {
+            // `legacy` and `none` children have no mountpoint to compare with
+            let own_mountpoint = Path::new(&d.mountpoint);
+            if !own_mountpoint.is_absolute() {
+                return None;
+            }
+            let mountpoint = match parent_mountpoint {
+                Some(parent) => mount
+                    .mount_point
+                    .join(own_mountpoint.strip_prefix(parent).ok()?),
+                None => own_mountpoint.to_path_buf(),
+            };
+            (path.starts_with(&mountpoint) && mountpoint != mount.mount_point)
+                .then_some((mountpoint, d))
+        })
+        .max_by_key(|(mountpoint, _)| mountpoint.components().count())
+        .map(|(_, d)| d)
+}
+
+/// Find the mount the path resides on, which must be a ZFS dataset. The mountpoint is taken from
+/// `/proc/self/mountinfo`, so it is correct inside other mount namespaces and for datasets with
+/// `mountpoint=legacy`, which are mounted through fstab.
+fn find_mount_in(mounts: &[Mount], path: &Path) -> Result<Mount> {
+    let Some(mount) = Mount::containing(mounts, path) else {
+        bail!("file does not reside under any ZFS dataset")
+    };
+    if mount.fstype != "zfs" {
+        bail!(
+            "file does not reside under any ZFS dataset, {:?} is a {} mount",
+            mount.mount_point,
+            mount.fstype
+        );
+    }
+    if mount.source.contains('@') {
+        bail!(
+            "{:?} is a mounted snapshot, not a dataset",
+            mount.mount_point
+        );
+    }
+    // the snapshots are only found in `.zfs/snapshot` at the root of the dataset
+    if mount.root != Path::new("/") {
+        bail!(
+            "{:?} is a bind mount of {} in {}, give the path below the mountpoint of the dataset",
+            mount.mount_point,
+            mount.root.display(),
+            mount.source
+        );
+    }
+    Ok(mount.clone())
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::{find_mount_in, unmounted_child, ZfsProvider};
+    use crate::zfs::cmd::{fake_zfs, DatasetInfo, ZfsCommand};
+    use crate::zfs::encryption::KeyNotLoaded;
+    use crate::zfs::mountinfo::Mount;
+
+    #[test]
+    fn find_dataset_in_mounts() {
+        let mount = |mount_point: &str, fstype: &str, source: &str| Mount {
+            root: "/".into(),
+            mount_point: mount_point.into(),
+            fstype: fstype.to_owned(),
+            source: source.to_owned(),
+        };
+        let mounts = [
+            mount("/", "ext4", "/dev/sda1"),
+            mount("/tank", "zfs", "tank"),
+            mount("/tank/home", "zfs", "tank/home"),
+            mount("/tank/home/.zfs/snapshot/s1", "zfs", "tank/home@s1"),
+            mount("/tank/tmp", "tmpfs", "tmpfs"),
+            mount("/tank/home", "zfs", "tank/home2"),
+            // `mountpoint=legacy`, mounted through fstab
+            mount("/var", "zfs", "rpool/ROOT/var"),
+            Mount {
+                root: "/www".into(),
+                ..mount("/srv/www", "zfs", "tank/data")
+            },
+        ];
+        let find = |path: &str| find_mount_in(&mounts, Path::new(path)).map(|m| m.source);
+
+        assert_eq!(find("/tank/home/user/file").unwrap(), "tank/home2");
+        assert_eq!(find("/tank/file").unwrap(), "tank");
+        assert!(find("/tank/tmp/file").is_err());
+        assert!(find("/etc/passwd").is_err());
+        assert_eq!(find("/var/log/syslog").unwrap(), "rpool/ROOT/var");
+        assert!(find("/tank/home/.zfs/snapshot/s1/file").is_err());
+        let error = find("/srv/www/index.html").unwrap_err().to_string();
+        assert!(error.contains("bind mount of /www in tank/data"), "{error}");
+    }
+
+    fn dataset(name: &str, mountpoint: &str) -> DatasetInfo {
+        DatasetInfo {
+            name: name.to_owned(),
+            mountpoint: mountpoint.to_owned(),
+            keystatus: "-".to_owned(),
+            encryption_root: "-".to_owned(),
+        }
+    }
+
+    #[test]
+    fn check_datasets_with_zfs_list() {
+        let dir = tempfile::tempdir().unwrap();
+        let provider = |script: &str| {
+            ZfsProvider::new(ZfsCommand {
+                binary: fake_zfs(dir.path(), "zfs", script),
+                prefix: vec![],
+            })
+        };
+        // mounted elsewhere than its mountpoint property, like in a container
+        let mounts = [Mount {
+            root: "/".into(),
+            mount_point: "/mnt/home".into(),
+            fstype: "zfs".to_owned(),
+            source: "tank/home".to_owned(),
+        }];
+        let file = Path::new("/mnt/home/alice/report.txt");
+
+        let listing = r"printf 'tank/home\t/tank/home\t-\t-\n'
+printf 'tank/home/alice\t/tank/home/alice\t%s\ttank/home/alice\n' ${ALICE_KEY:-available}";
+        let (volume, live_path) = provider(listing).find_volume_in(&mounts, file).unwrap();
+        assert_eq!(volume.name, "tank/home");
+        assert_eq!(live_path, file);
+
+        // alice is not mounted because her key is not loaded
+        let locked = listing.replace("${ALICE_KEY:-available}", "unavailable");
+        let error = provider(&locked).find_volume_in(&mounts, file).unwrap_err();
+        let locked = error.downcast_ref::<KeyNotLoaded>().unwrap();
+        assert_eq!(locked.dataset, "tank/home/alice");
+        assert_eq!(locked.encryption_root, "tank/home/alice");
+
+        let other = r"printf 'tank/other\t/tank/other\t-\t-\n'";
+        assert!(provider(other).find_volume_in(&mounts, file).is_err());
+        // without a usable zfs, mountinfo is enough
+        assert!(provider("exit 1").find_volume_in(&mounts, file).is_ok());
+
+        let datasets = [
+            dataset("tank/home", "/tank/home"),
+            dataset("tank/home/alice", "/tank/home/alice"),
+            dataset("tank/home/alice/old", "/tank/home/alice/old"),
+            dataset("tank/home/bob", "none"),
+        ];
+        let child = |path: &str| {
+            unmounted_child(&mounts[0], &datasets[0], &datasets, Path::new(path))
+                .map(|d| d.name.as_str())
+        };
+        assert_eq!(child("/mnt/home/alice/old/a"), Some("tank/home/alice/old"));
+        assert_eq!(child("/mnt/home/alice/a"), Some("tank/home/alice"));
+        assert_eq!(child("/mnt/home/bob/a"), None);
+        assert_eq!(child("/mnt/home/a"), None);
+    }
+
+    /// Datasets with `mountpoint=legacy` mounted through fstab, as in `/proc/self/mountinfo`.
+    const LEGACY_MOUNTINFO: &str = "\
+22 1 0:21 / / rw,relatime shared:1 - zfs rpool/ROOT/default rw,xattr,posixacl
+23 22 0:22 / /var rw,relatime shared:2 - zfs rpool/var rw,xattr,posixacl
+24 22 0:5 / /proc rw,nosuid,nodev,noexec,relatime shared:3 - proc proc rw
+25 22 0:23 / /home rw,relatime shared:4 - zfs rpool/home rw,xattr,posixacl
+";
+
+    #[test]
+    fn find_legacy_datasets() {
+        let dir = tempfile::tempdir().unwrap();
+        let listing = r#"case "$9" in
+rpool/ROOT/default) printf 'rpool/ROOT/default\tlegacy\t-\t-\n' ;;
+rpool/var) printf 'rpool/var\tlegacy\t-\t-\nrpool/var/log\tlegacy\t-\t-\n'
+    printf 'rpool/var/lib\t/var/lib\t-\t-\n' ;;
+*) exit 1 ;;
+esac"#;
+        let provider = ZfsProvider::new(ZfsCommand {
+            binary: fake_zfs(dir.path(), "zfs", listing),
+            prefix: vec![],
+        });
+        let mounts = Mount::parse_all(LEGACY_MOUNTINFO).unwrap();
+        let find = |path: &str| {
+            provider
+                .find_volume_in(&mounts, Path::new(path))
+                .map(|(volume, _)| volume)
+        };
+
+        let volume = find("/var/log/syslog").unwrap();
+        assert_eq!(volume.name, "rpool/var");
+        assert_eq!(volume.path, Path::new("/var"));
+        assert_eq!(find("/etc/fstab").unwrap().name, "rpool/ROOT/default");
+        assert!(find("/proc/self/status").is_err());
+
+        let datasets = [
+            dataset("rpool/var", "legacy"),
+            dataset("rpool/var/log", "legacy"),
+            dataset("rpool/var/lib", "/var/lib"),
+        ];
+        let child = |path: &str| {
+            unmounted_child(&mounts[1], &datasets[0], &datasets, Path::new(path))
+                .map(|d| d.name.as_str())
+        };
+        assert_eq!(child("/var/lib/dpkg/status"), Some("rpool/var/lib"));
+        assert_eq!(child("/var/log/syslog"), None);
+    }
+}
@@ -0,0 +1,337 @@
+use std::collections::{HashMap, HashSet};
+use std::ffi::{CString, OsStr, OsString};
+use std::fs::{self, File, Permissions};
+use std::io;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::{lchown, symlink, PermissionsExt};
+use std::path::Path;
+use std::rc::Rc;
+
+use anyhow::{bail, Context, Result};
+
+use super::sendstream::{ByteOrder, Object, ObjectSet};
+use super::zap::{read_entries, ZapEntry};
+
+/// The master node, which points to the root directory and the attribute layouts.
+const MASTER_NODE: u64 = 1;
+/// The bonus type of objects whose attributes are stored as system attributes.
+const BONUS_TYPE_SA: u32 = 44;
+/// The bonus type of objects with a `znode_phys_t`, used before ZPL version 5.
+const BONUS_TYPE_ZNODE: u32 = 17;
+const SA_MAGIC: u32 = 0x2F505A;
+/// The size of `znode_phys_t`, after which short symlink targets are stored.
+const ZNODE_SIZE: usize = 264;
+/// The object number in the lower bits of a directory entry.
+const DIRENT_OBJECT_MASK: u64 = (1 << 48) - 1;
+
+const S_IFMT: u64 = 0o170000;
+const S_IFDIR: u64 = 0o040000;
+const S_IFREG: u64 = 0o100000;
+const S_IFLNK: u64 = 0o120000;
+const S_IFIFO: u64 = 0o010000;
+
+/// The attributes of a file, directory or symlink.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct Znode {
+    pub(super) mode: u64,
+    pub(super) size: u64,
+    /// Seconds and nanoseconds since the epoch.
+    pub(super) mtime: (i64, i64),
+    pub(super) uid: u64,
+    pub(super) gid: u64,
+    /// The target of a symlink, if it is stored with the attributes.
+    symlink: Option<Vec<u8>>,
+}
+
+/// The layouts of system attributes: which attributes are stored in which order.
+#[derive(Debug, Default)]
+struct Layouts {
+    /// The name and the length of each attribute by its number. Variable-length attributes have
+    /// a length of 0.
+    attributes: HashMap<u64, (String, usize)>,
+    layouts: HashMap<u64, Vec<u64>>,
+}
+
+/// The files of a snapshot received from `zfs send` streams.
+#[derive(Debug)]
+pub(super) struct Filesystem<'a> {
+    objects: &'a ObjectSet,
+    root: u64,
+    layouts: Layouts,
+}
+
+impl<'a> Filesystem<'a> {
+    pub(super) fn open(objects: &'a ObjectSet) -> Result<Self> {
+        let master = Self::zap(objects, MASTER_NODE).context("could not read the master node")?;
+        let value = |name: &[u8]| {
+            master
+                .iter()
+                .find(|entry| entry.name == name)
+                .and_then(|entry| entry.value.first().copied())
+        };
+        let root = value(b"ROOT").context("the master node has no root directory")?;
+        let layouts = match value(b"SA_ATTRS") {
+            Some(id) => Self::read_layouts(objects, id).context("could not read SA layouts")?,
+            None => Layouts::default(),
+        };
+
+        Ok(Self {
+            objects,
+            root,
+            layouts,
+        })
+    }
+
+    fn zap(objects: &ObjectSet, id: u64) -> Result<Vec<ZapEntry>> {
+        let object = objects.object(id)?;
+        read_entries(
+            &objects.read_blocks(id)?,
+            object.block_size as usize,
+            objects.byte_order,
+        )
+    }
+
+    fn read_layouts(objects: &ObjectSet, id: u64) -> Result<Layouts> {
+        let master = Self::zap(objects, id)?;
+        let value = |name: &[u8]| {
+            master
+                .iter()
+                .find(|entry| entry.name == name)
+                .and_then(|entry| entry.value.first().copied())
+                .with_context(|| format!("missing {}", String::from_utf8_lossy(name)))
+        };
+
+        // the number in the lowest 16 bits, the length in bits 24 to 40
+        let attributes = Self::zap(objects, value(b"REGISTRY")?)?
+            .into_iter()
+            .filter_map(|entry| {
+                let registration = *entry.value.first()?;
+                let name = String::from_utf8_lossy(&entry.name).into_owned();
+                Some((
+                    registration & 0xffff,
+                    (name, (registration >> 24 & 0xffff) as usize),
+                ))
+            })
+            .collect();
+        let layouts = Self::zap(objects, value(b"LAYOUTS")?)?
+            .into_iter()
+            .filter_map(|entry| {
+                let number = std::str::from_utf8(&entry.name).ok()?.parse().ok()?;
+                Some((number, entry.value))
+            })
+            .collect();
+
+        Ok(Layouts {
+            attributes,
+            layouts,
+        })
+    }
+
+    /// Find the object of the path relative to the root directory, if it exists.
+    pub(super) fn lookup(&self, path: &Path) -> Result<Option<u64>> {
+        let mut id = self.root;
+        for name in path.iter() {
+            if self.znode(id)?.mode & S_IFMT != S_IFDIR {
+                return Ok(None);
+            }
+            match self.entries(id)?.into_iter().find(|(n, _)| n == name) {
+                Some((_, child)) => id = child,
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(id))
+    }
+
+    /// The names and objects of the entries of a directory.
+    fn entries(&self, directory: u64) -> Result<Vec<(OsString, u64)>> {
+        Ok(Self::zap(self.objects, directory)?
+            .into_iter()
+            .filter_map(|entry| {
+                let value = entry.value.first()?;
+                Some((OsString::from_vec(entry.name), value & DIRENT_OBJECT_MASK))
+            })
+            .collect())
+    }
+
+    /// Read the attributes of the object from its bonus buffer.
+    pub(super) fn znode(&self, id: u64) -> Result<Znode> {
+        let object = self.objects.object(id)?;
+        let order = self.objects.byte_order;
+        let bonus = &object.bonus;
+        match object.bonus_type {
+            BONUS_TYPE_SA => self.read_system_attributes(bonus, order),
+            BONUS_TYPE_ZNODE => {
+                let size = order.u64(bonus, 80)?;
+                Ok(Znode {
+                    mtime: (order.u64(bonus, 16)? as i64, order.u64(bonus, 24)? as i64),
+                    mode: order.u64(bonus, 72)?,
+                    size,
+                    uid: order.u64(bonus, 128)?,
+                    gid: order.u64(bonus, 136)?,
+                    symlink: usize::try_from(size)
+                        .ok()
+                        .and_then(|size| ZNODE_SIZE.checked_add(size))
+                        .and_then(|end| bonus.get(ZNODE_SIZE..end))
+                        .map(<[u8]>::to_vec),
+                })
+            }
+            other => bail!("object {id} has unknown bonus type {other}"),
+        }
+    }
+
+    /// Read system attributes: a header with the layout and the lengths of variable-length
+    /// attributes, followed by the attributes in the order of the layout, 8 byte aligned.
+    fn read_system_attributes(&self, bonus: &[u8], order: ByteOrder) -> Result<Znode> {
+        if order.u32(bonus, 0)? != SA_MAGIC {
+            bail!("invalid system attribute header");
+        }
+        let layout_info = order.u16(bonus, 4)?;
+        let layout = u64::from(layout_info & 0x3ff);
+        let mut offset = usize::from(layout_info >> 10) * 8;
+        let mut variable_lengths = 0;
+
+        let mut attributes = HashMap::new();
+        for attribute in self
+            .layouts
+            .layouts
+            .get(&layout)
+            .with_context(|| format!("unknown SA layout {layout}"))?
+        {
+            let (name, length) = self
+                .layouts
+                .attributes
+                .get(attribute)
+                .with_context(|| format!("unknown system attribute {attribute}"))?;
+            let length = match length {
+                0 => {
+                    variable_lengths += 1;
+                    usize::from(order.u16(bonus, 4 + 2 * variable_lengths)?)
+                }
+                length => *length,
+            };
+            let value = bonus
+                .get(offset..offset + length)
+                .context("system attributes are truncated")?;
+            attributes.insert(name.as_str(), value);
+            offset += length.next_multiple_of(8);
+        }
+
+        let number = |name: &str, index: usize| -> Result<u64> {
+            let value = attributes
+                .get(name)
+                .with_context(|| format!("missing system attribute {name}"))?;
+            order.u64(value, 8 * index)
+        };
+        Ok(Znode {
+            mode: number("ZPL_MODE", 0)?,
+            size: number("ZPL_SIZE", 0)?,
+            mtime: (
+                number("ZPL_MTIME", 0)? as i64,
+                number("ZPL_MTIME", 1)? as i64,
+            ),
+            uid: number("ZPL_UID", 0)?,
+            gid: number("ZPL_GID", 0)?,
+            symlink: attributes.get("ZPL_SYMLINK").map(|target| target.to_vec()),
+        })
+    }
+
+    /// The object and, for a directory, the objects below it, to tell whether any of them changed
+    /// between snapshots.
+    pub(super) fn tree(&self, id: u64) -> Result<Vec<(u64, Rc<Object>)>> {
+        let mut result = vec![];
+        let mut directories = vec![id];
+        let mut visited = HashSet::new();
+        while let Some(id) = directories.pop() {
+            result.push((id, self.objects.shared_object(id)?.clone()));
+            if self.znode(id)?.mode & S_IFMT != S_IFDIR {
+                continue;
+            }
+            if !visited.insert(id) {
+                bail!("directory {id} is reached twice, the directory tree is corrupt");
+            }
+            directories.extend(self.entries(id)?.into_iter().map(|(_, child)| child));
+        }
+        Ok(result)
+    }
+
+    /// Recreate the object at `target`, with the contents of directories. Sockets and device
+    /// nodes are skipped.
+    pub(super) fn extract(&self, id: u64, target: &Path) -> Result<()> {
+        self.extract_into(id, target, &mut HashSet::new())
+    }
+
+    /// Extract the object, remembering the directories extracted so far, so that a corrupt
+    /// stream whose directories contain each other cannot recurse forever.
+    fn extract_into(&self, id: u64, target: &Path, visited: &mut HashSet<u64>) -> Result<()> {
+        let znode = self.znode(id)?;
+        match znode.mode & S_IFMT {
+            S_IFDIR => {
+                if !visited.insert(id) {
+                    bail!("directory {id} is reached twice, the directory tree is corrupt");
+                }
+                fs::create_dir(target).with_context(|| format!("could not create {target:?}"))?;
+                for (name, child) in self.entries(id)? {
+                    self.extract_into(child, &target.join(name), visited)?;
+                }
+            }
+            S_IFREG => {
+                let file =
+                    File::create(target).with_context(|| format!("could not create {target:?}"))?;
+                self.objects.copy_to(id, znode.size, &file)?;
+            }
+            S_IFLNK => {
+                let link = match &znode.symlink {
+                    Some(link) => link.clone(),
+                    None => self.objects.read(id, znode.size)?,
+                };
+                symlink(OsStr::from_bytes(&link), target)
+                    .with_context(|| format!("could not create {target:?}"))?;
+            }
+            S_IFIFO => make_fifo(target)?,
+            _ => return Ok(()),
+        }
+
+        if znode.mode & S_IFMT != S_IFLNK {
+            fs::set_permissions(target, Permissions::from_mode((znode.mode & 0o7777) as u32))?;
+        }
+        // SAFETY: `geteuid` has no preconditions and cannot fail
+        if unsafe { libc::geteuid() } == 0 {
+            lchown(target, Some(znode.uid as u32), Some(znode.gid as u32))?;
+        }
+        set_mtime(target, znode.mtime)
+    }
+}
+
+fn make_fifo(path: &Path) -> Result<()> {
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    // SAFETY: `c_path` is a valid C string
+    if unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) } != 0 {
+        return Err(io::Error::last_os_error())
+            .with_context(|| format!("could not create {path:?}"));
+    }
+    Ok(())
+}
+
+/// Set the access and modification time of the file, without following symlinks.
+fn set_mtime(path: &Path, (seconds, nanoseconds): (i64, i64)) -> Result<()> {
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    let time = libc::timespec {
+        tv_sec: seconds as libc::time_t,
+        tv_nsec: nanoseconds as _,
+    };
+    let times = [time, time];
+    // SAFETY: `c_path` is a valid C string and `times` holds two timestamps
+    let result = unsafe {
+        libc::utimensat(
+            libc::AT_FDCWD,
+            c_path.as_ptr(),
+            times.as_ptr(),
+            libc::AT_SYMLINK_NOFOLLOW,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error())
+            .with_context(|| format!("could not set the time of {path:?}"));
+    }
+    Ok(())
+}